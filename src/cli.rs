@@ -48,15 +48,187 @@ pub enum Subcommands {
         path: Option<String>,
     },
     /// Clean out conflicting PRs and requeue failed PRs
-    Housekeeping,
+    Housekeeping(Housekeeping),
     /// Simulate a test with flake rate in consideration
-    TestSim,
+    TestSim(TestSim),
     /// Generate pull requests
-    Generate,
+    Generate(Generate),
     /// upload targets
     UploadTargets(UploadTargets),
     /// Enqueue a pull request
     Enqueue(Enqueue),
+    /// Take a pull request back out of the merge queue
+    Dequeue(Dequeue),
+    /// Inspect, pause, drain or resume the Trunk merge queue
+    Queue(Queue),
+    /// Simulate the merge queue offline and report the throughput and latency it would achieve
+    Simulate(Simulate),
+    /// Run the offline simulator over ranges of parameters and print a table of the results
+    Sweep(Sweep),
+    /// Run the phases of a scenario file in order, generating PRs with each phase's config
+    RunScenario(RunScenario),
+}
+
+#[derive(Parser, Debug)]
+pub struct Housekeeping {
+    /// Keep running, repeating housekeeping every --interval until SIGTERM/SIGINT
+    #[clap(long)]
+    pub watch: bool,
+
+    /// Time between housekeeping passes in watch mode (e.g. "5m", "30 seconds")
+    #[clap(long, default_value = "5m")]
+    pub interval: String,
+}
+
+#[derive(Parser, Debug)]
+pub struct Generate {
+    /// Keep generating windows of PRs back to back until SIGTERM/SIGINT instead of exiting
+    /// after one; failed windows are retried with backoff
+    #[clap(long)]
+    pub daemon: bool,
+
+    /// Heartbeat file the daemon keeps updated with its status
+    #[clap(long, default_value = "mq-heartbeat.json")]
+    pub heartbeat: String,
+
+    /// How often the daemon rewrites the heartbeat file
+    #[clap(long = "heartbeat-interval", default_value = "30s")]
+    pub heartbeat_interval: String,
+
+    /// In burst mode (requests_per_run), pause the Trunk queues of the protected branches while
+    /// the PRs are created and resume them afterwards, so the whole burst arrives at once
+    #[clap(long = "pause-during-setup")]
+    pub pause_during_setup: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct TestSim {
+    /// Path to file that contains github-json block; impacted targets are read from its PR body
+    #[clap(long = "github-json")]
+    pub github_json: Option<String>,
+
+    /// Pull request number whose body lists the impacted targets (deps=[a,b])
+    #[clap(long)]
+    pub pr: Option<String>,
+
+    /// Ref to diff HEAD against when neither --github-json nor --pr is given; changed files
+    /// become the impacted targets. Defaults to origin/<first protected branch>
+    #[clap(long = "diff-base")]
+    pub diff_base: Option<String>,
+
+    /// Directory to write test-sim.xml (JUnit) and test-sim.json results to
+    #[clap(long = "results-dir")]
+    pub results_dir: Option<String>,
+
+    /// Seed for the random draws, to replay a previous run (printed by every run)
+    #[clap(long)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Parser, Debug)]
+pub struct Simulate {
+    /// Queue mode; defaults to the config `mode`
+    #[clap(long, value_parser = ["single", "parallel"])]
+    pub mode: Option<String>,
+
+    /// Maximum PRs tested together in one batch
+    #[clap(long = "batch-size", default_value_t = 1)]
+    pub batch_size: usize,
+
+    /// Maximum batches testing at once (how far ahead the queue tests speculatively)
+    #[clap(long, default_value_t = 1)]
+    pub concurrency: usize,
+
+    /// Merge a failed batch when a batch tested on top of it passes
+    #[clap(long)]
+    pub optimistic: bool,
+
+    /// Re-test a failed batch this many times before bisecting or ejecting it
+    #[clap(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// How long PRs keep arriving (e.g. "8 hours")
+    #[clap(long, default_value = "8 hours")]
+    pub duration: String,
+
+    /// Number of distinct targets PR dependencies are drawn from
+    #[clap(long, default_value_t = 26)]
+    pub targets: usize,
+
+    /// Seed for the random draws; the same seed and config give the same result
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Print the report as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Sweep {
+    /// Flake rates to sweep, as a list ("0.05,0.12") or range ("0.05..0.2:0.05");
+    /// defaults to the config `test.flake_rate`
+    #[clap(long = "flake-rate")]
+    pub flake_rate: Option<String>,
+
+    /// Batch sizes to sweep (e.g. "1,2,4,8" or "1..8")
+    #[clap(long = "batch-size", default_value = "1")]
+    pub batch_size: String,
+
+    /// Queue concurrencies to sweep (e.g. "1..10:3")
+    #[clap(long, default_value = "1")]
+    pub concurrency: String,
+
+    /// Arrival rates to sweep; defaults to the config arrival rate
+    #[clap(long = "requests-per-hour")]
+    pub requests_per_hour: Option<String>,
+
+    /// Queue mode; defaults to the config `mode`
+    #[clap(long, value_parser = ["single", "parallel"])]
+    pub mode: Option<String>,
+
+    /// Merge a failed batch when a batch tested on top of it passes
+    #[clap(long)]
+    pub optimistic: bool,
+
+    /// Re-test a failed batch this many times before bisecting or ejecting it
+    #[clap(long, default_value_t = 0)]
+    pub retries: u32,
+
+    /// How long PRs keep arriving in each simulation (e.g. "8 hours")
+    #[clap(long, default_value = "8 hours")]
+    pub duration: String,
+
+    /// Number of distinct targets PR dependencies are drawn from
+    #[clap(long, default_value_t = 26)]
+    pub targets: usize,
+
+    /// Seed shared by every combination, so rows differ only by their parameters
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Output format
+    #[clap(long, default_value = "csv", value_parser = ["csv", "json"])]
+    pub format: String,
+
+    /// Write the table to this file instead of stdout
+    #[clap(long)]
+    pub output: Option<String>,
+
+    /// Simulations to run at once; defaults to the number of CPU cores
+    #[clap(long)]
+    pub threads: Option<usize>,
+}
+
+#[derive(Parser, Debug)]
+pub struct RunScenario {
+    /// Scenario TOML file with ordered [[phase]] entries
+    #[arg(value_name = "SCENARIO")]
+    pub path: String,
+
+    /// Ledger file to record phase boundaries in; overrides the scenario's `ledger`
+    #[clap(long)]
+    pub ledger: Option<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -77,4 +249,65 @@ pub struct Enqueue {
     /// Pull request number to enqueue
     #[clap(short, long)]
     pub pr: String,
+
+    /// Queue priority: urgent, high, medium, low, or 0 (highest) to 255. Sent by the "api" and
+    /// "comment" merge triggers; the queue's default when unset.
+    #[clap(long, value_parser = parse_priority)]
+    pub priority: Option<String>,
+}
+
+fn parse_priority(s: &str) -> Result<String, String> {
+    if crate::trunk::is_valid_priority(s) {
+        Ok(s.to_string())
+    } else {
+        Err("expected urgent, high, medium, low or a number from 0 to 255".to_string())
+    }
+}
+
+#[derive(Parser, Debug)]
+pub struct Queue {
+    #[command(subcommand)]
+    pub command: QueueCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum QueueCommand {
+    /// Print the state, position and priority of queued PRs from the Trunk API
+    Status(QueueStatus),
+    /// Pause the queue: PRs can still be enqueued but none are tested or merged
+    Pause(QueueBranch),
+    /// Drain the queue: finish the PRs already queued without accepting new ones
+    Drain(QueueBranch),
+    /// Resume a paused or draining queue
+    Resume(QueueBranch),
+}
+
+#[derive(Parser, Debug)]
+pub struct QueueBranch {
+    /// Branch the queue merges into; defaults to the first protected branch
+    #[clap(long)]
+    pub branch: Option<String>,
+}
+
+#[derive(Parser, Debug)]
+pub struct QueueStatus {
+    /// Branch the queue merges into; defaults to the first protected branch
+    #[clap(long)]
+    pub branch: Option<String>,
+
+    /// Only report these PRs, including whether their impacted targets were uploaded
+    /// (can be specified multiple times)
+    #[clap(long)]
+    pub pr: Vec<u32>,
+
+    /// Print JSON instead of a table
+    #[clap(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Parser, Debug)]
+pub struct Dequeue {
+    /// Pull request number to dequeue
+    #[clap(short, long)]
+    pub pr: String,
 }
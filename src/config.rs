@@ -1,9 +1,12 @@
+use crate::distribution::DurationDistribution;
 use confique::toml::{self, FormatOptions};
 use confique::Config;
 use parse_duration::parse;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum Mode {
     #[default]
@@ -11,7 +14,7 @@ pub enum Mode {
     ParallelQueue,
 }
 
-#[derive(Config, Serialize, Default)]
+#[derive(Config, Serialize, Default, Clone)]
 pub struct Conf {
     #[config(default = "singlequeue")]
     pub mode: Mode,
@@ -33,15 +36,18 @@ pub struct Conf {
 
     #[config(nested)]
     pub merge: MergeConf,
+
+    #[config(nested)]
+    pub stack: StackConf,
 }
 
-#[derive(Config, Serialize, Default)]
+#[derive(Config, Serialize, Default, Clone)]
 pub struct TrunkConf {
     #[config(default = "api.trunk.io")]
     pub api: String,
 }
 
-#[derive(Config, Serialize, Default)]
+#[derive(Config, Serialize, Default, Clone)]
 pub struct GitConf {
     #[config(default = "Jane Doe")]
     pub name: String,
@@ -50,7 +56,7 @@ pub struct GitConf {
     pub email: String,
 }
 
-#[derive(Config, Serialize, Default)]
+#[derive(Config, Serialize, Default, Clone)]
 pub struct PullRequestConf {
     #[config(default = "")]
     pub labels: String,
@@ -101,36 +107,266 @@ pub struct PullRequestConf {
     #[config(default = "logical-conflict.txt")]
     pub logical_conflict_file: String,
 
+    /// Every Nth PR starts a pairwise logical conflict that the next PR outside its stack
+    /// completes. Each PR passes on its own; test-sim fails only when both are in the tested
+    /// tree. 0 disables.
+    #[config(default = 0)]
+    pub pairwise_conflict_every: u32,
+
+    /// Directory the pairwise conflict marker files are written to
+    #[config(default = "logical-conflicts")]
+    pub pairwise_conflict_dir: String,
+
+    /// Fraction of generated PRs enqueued with a priority picked at random from `priorities`
+    /// instead of the queue's default. Only the "api" and "comment" triggers send a priority.
+    #[config(default = 0.0)]
+    pub priority_rate: f32,
+
+    /// Trunk priorities: "urgent", "high", "medium", "low", or 0 (highest) to 255
+    #[config(default = ["high"])]
+    pub priorities: Vec<String>,
+
+    /// Fraction of enqueued PRs dequeued again after `cancel_after`, as if the author changed
+    /// their mind
+    #[config(default = 0.0)]
+    pub cancel_rate: f32,
+
+    #[config(default = "5 minutes")]
+    pub cancel_after: String,
+
+    /// Fraction of enqueued PRs that get another change pushed to their branch `update_after`
+    /// later, while they are in the queue
+    #[config(default = 0.0)]
+    pub update_rate: f32,
+
+    /// Of the updated PRs, the fraction whose head commit is amended and force-pushed instead of
+    /// getting a new commit on top. PRs with others stacked on them always get a new commit.
+    #[config(default = 0.5)]
+    pub update_force_push_rate: f32,
+
+    #[config(default = "10 minutes")]
+    pub update_after: String,
+
+    /// Delay between creating a PR and enqueuing it, as a fixed duration or distribution like
+    /// `test.sleep_for` (e.g. "lognormal(20m, 0.8)"). Delayed enqueues run in the background.
+    /// Unset enqueues right away.
+    pub enqueue_delay: Option<String>,
+
+    /// Before enqueuing, wait for the PR's required checks to finish (pass or fail)
+    #[config(default = false)]
+    pub enqueue_wait_for_checks: bool,
+
+    /// Enqueue anyway when required checks are still running this long after the delay
+    #[config(default = "2 hours")]
+    pub enqueue_checks_timeout: String,
+
     #[config(default = ["removed from the merge queue", "To merge this pull request, check the box to the left", "/trunk merge"])]
     pub detect_stale_pr_comments: Vec<String>,
 
     #[config(default = "4 hours")]
     pub close_stale_after: String,
 
+    /// How long housekeeping keeps polling PRs whose mergeability GitHub reports as UNKNOWN
+    #[config(default = "2 minutes")]
+    pub mergeable_poll_timeout: String,
+
+    /// Delay before the first mergeability re-poll. Doubles after every poll, up to 1 minute.
+    #[config(default = "2 seconds")]
+    pub mergeable_poll_interval: String,
+
     #[config(default = ["main"])]
     pub protected_branches: Vec<String>,
 }
 
-#[derive(Config, Serialize, Default)]
+#[derive(Config, Serialize, Default, Clone)]
 pub struct TestConf {
     #[config(default = 0.1)]
     pub flake_rate: f32,
 
+    /// Fixed duration ("1 second") or distribution to sample each run's duration from:
+    /// "uniform(30s, 90s)", "normal(60s, 10s)", "lognormal(60s, 0.5)" (median, sigma) or
+    /// "empirical(durations.txt)" (one observed duration per line, optionally ",weight")
     #[config(default = "1 second")]
     pub sleep_for: String,
+
+    /// Fraction of the sampled duration added for each impacted target beyond the first,
+    /// e.g. 0.5 makes a run with 3 impacted targets take twice as long. 0 disables scaling.
+    #[config(default = 0.0)]
+    pub sleep_scale_per_target: f32,
+
+    /// Branch name patterns (regular expressions) that mark a test-sim run as a merge queue run.
+    /// Checked against GITHUB_REF and the ref in `--github-json`; GitHub `merge_group` events
+    /// always count. IS_MERGE=true/false overrides detection.
+    #[config(default = ["^trunk-merge/", "^gh-readonly-queue/"])]
+    pub merge_branch_patterns: Vec<String>,
+
+    /// CI timeout for a test run. Runs whose sampled duration exceeds it, and hung runs, sleep
+    /// until the timeout and exit with code 124. Unset means 6 hours, GitHub Actions' job limit.
+    pub timeout: Option<String>,
+
+    /// Probability that a run hangs until `timeout` instead of finishing
+    #[config(default = 0.0)]
+    pub hang_rate: f32,
+
+    /// Derive the flake draw from the tested commit SHA instead of fresh randomness, so
+    /// re-running the same commit fails (or passes) the same way every time
+    #[config(default = false)]
+    pub sticky_flakes: bool,
+
+    /// Probability that a flaky failure passes anyway when the run is a retry
+    /// (GITHUB_RUN_ATTEMPT > 1)
+    #[config(default = 0.0)]
+    pub retry_pass_rate: f32,
+
+    /// Simulated infrastructure outages: once every `outage_every`, for `outage_duration`,
+    /// runs get an extra `outage_flake_rate` chance of failing. Windows are derived from
+    /// wall-clock time so concurrent runs agree on them. Unset disables outages.
+    pub outage_every: Option<String>,
+
+    #[config(default = "5 minutes")]
+    pub outage_duration: String,
+
+    #[config(default = 0.5)]
+    pub outage_flake_rate: f32,
+
+    /// Directory test-sim writes `test-sim.xml` (JUnit) and `test-sim.json` results to.
+    /// Unset disables result files; `--results-dir` overrides it.
+    pub results_dir: Option<String>,
+
+    /// Per-target overrides keyed by target letter (as in `deps=[a,b]`) or changed file path.
+    /// Each impacted target listed here adds its own chance of failing on top of `flake_rate`.
+    /// Example:
+    ///   [test.targets.p]
+    ///   flake_rate = 0.3
+    ///   sleep_for = "5 seconds"
+    pub targets: Option<BTreeMap<String, TargetConf>>,
+
+    /// Per-base-branch overrides of this section, keyed by the branch a PR targets. test-sim
+    /// picks the profile from the base branch in `--github-json` or of `--pr`.
+    /// Example:
+    ///   [test.branches.release]
+    ///   flake_rate = 0.02
+    ///   sleep_for = "10 minutes"
+    pub branches: Option<BTreeMap<String, TestProfile>>,
+}
+
+/// Overrides applied to `[test]` for PRs into one base branch; unset fields keep the
+/// `[test]` value. `targets` entries are merged into `test.targets` by key.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TestProfile {
+    #[serde(default)]
+    pub flake_rate: Option<f32>,
+    #[serde(default)]
+    pub sleep_for: Option<String>,
+    #[serde(default)]
+    pub sleep_scale_per_target: Option<f32>,
+    #[serde(default)]
+    pub timeout: Option<String>,
+    #[serde(default)]
+    pub hang_rate: Option<f32>,
+    #[serde(default)]
+    pub retry_pass_rate: Option<f32>,
+    #[serde(default)]
+    pub targets: Option<BTreeMap<String, TargetConf>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TargetConf {
+    pub flake_rate: f32,
+
+    /// Optional duration or distribution for this target (same syntax as `test.sleep_for`);
+    /// the slowest impacted target sets the test duration
+    #[serde(default)]
+    pub sleep_for: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum EnqueueTrigger {
     #[default]
     Comment, // post comment to PR to enqueue
-    Label, // add a label to PR to enqueue
-    Run,   // run a command to enqueue PR i.e. - gh pr merge {{PR_NUMBER}}
-    Api,   // use Trunk API to enqueue PR
+    Label,    // add a label to PR to enqueue
+    Run,      // run a command to enqueue PR i.e. - gh pr merge {{PR_NUMBER}}
+    Api,      // use Trunk API to enqueue PR
+    Github,   // use GitHub's native merge queue (see `merge.github_method`)
+    Mergify,  // comment `@mergifyio queue` to enqueue
+    Bors,     // comment `bors r+` to enqueue
+    Graphite, // add `merge.labels` for Graphite's merge queue to pick up
+}
+
+/// `merge.run`: a command line or an argv array.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum RunCommand {
+    Line(String),
+    Argv(Vec<String>),
+}
+
+impl Default for RunCommand {
+    fn default() -> Self {
+        RunCommand::Line(String::new())
+    }
+}
+
+impl RunCommand {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            RunCommand::Line(line) => line.trim().is_empty(),
+            RunCommand::Argv(argv) => argv.is_empty(),
+        }
+    }
+
+    /// The argument templates to run. A line is split on whitespace, or with `shell` handed to
+    /// `sh -c` whole.
+    pub fn templates(&self, shell: bool) -> Vec<&str> {
+        match self {
+            RunCommand::Line(line) if shell => vec!["sh", "-c", line.as_str()],
+            RunCommand::Line(line) => line.split_whitespace().collect(),
+            RunCommand::Argv(argv) => argv.iter().map(|a| a.as_str()).collect(),
+        }
+    }
+}
+
+/// Which PRs of a generated stack get enqueued, and in what order.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StackEnqueue {
+    /// Enqueue only the tip, as a stack (`/trunk stack` for the comment trigger)
+    #[default]
+    Top,
+    /// Enqueue every PR on its own as soon as it is created
+    Each,
+    /// Once the stack is complete, enqueue every PR on its own from the bottom up,
+    /// `enqueue_interval` apart
+    BottomUp,
+    /// Once the stack is complete, enqueue every PR on its own in shuffled order,
+    /// `enqueue_interval` apart
+    RandomOrder,
+}
+
+#[derive(Config, Serialize, Default, Clone)]
+pub struct StackConf {
+    /// "top", "each", "bottom-up" or "random-order"
+    #[config(default = "top")]
+    pub enqueue: StackEnqueue,
+
+    /// Gap between enqueuing consecutive stack members for "bottom-up" and "random-order"
+    #[config(default = "30 seconds")]
+    pub enqueue_interval: String,
+}
+
+/// How the `github` trigger puts a PR into GitHub's merge queue.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GitHubEnqueueMethod {
+    /// `enqueuePullRequest` mutation: joins the queue now, fails if the PR is not ready
+    #[default]
+    Enqueue,
+    /// `enablePullRequestAutoMerge` mutation: joins the queue once required checks pass
+    AutoMerge,
 }
 
-#[derive(Config, Serialize, Default)]
+#[derive(Config, Serialize, Default, Clone)]
 pub struct MergeConf {
     #[config(default = "comment")]
     pub trigger: EnqueueTrigger,
@@ -141,8 +377,31 @@ pub struct MergeConf {
     #[config(default = "")]
     pub comment: String,
 
+    /// Command for trigger = "run": a string split on whitespace, or an argv array whose
+    /// elements are passed as-is
     #[config(default = "")]
-    pub run: String,
+    pub run: RunCommand,
+
+    /// Run `run` (a string) with `sh -c`, so quoting, pipes and `$MQ_*` variables work
+    #[config(default = false)]
+    pub run_shell: bool,
+
+    /// The run command is killed after this long
+    #[config(default = "5 minutes")]
+    pub run_timeout: String,
+
+    /// Retries after a failed or timed out run command
+    #[config(default = 2)]
+    pub run_retries: u32,
+
+    /// Delay before the first retry; doubles for each further retry
+    #[config(default = "10 seconds")]
+    pub run_retry_delay: String,
+
+    /// For `trigger = "github"`: "enqueue" adds the PR to the merge queue immediately,
+    /// "auto-merge" enables auto-merge so it joins the queue once its checks pass
+    #[config(default = "enqueue")]
+    pub github_method: GitHubEnqueueMethod,
 }
 
 impl Conf {
@@ -151,8 +410,157 @@ impl Conf {
         println!("{}", default_config);
     }
 
-    pub fn sleep_duration(&self) -> std::time::Duration {
-        parse(&self.test.sleep_for).expect("Failed to parse sleep_for into a Duration")
+    pub fn sleep_distribution(&self) -> DurationDistribution {
+        DurationDistribution::parse(&self.test.sleep_for)
+            .expect("Failed to parse sleep_for into a duration distribution")
+    }
+
+    /// `[test.targets]` entries that apply to the impacted targets. A key applies when it equals
+    /// an impacted target or matches the trailing components of an impacted file path.
+    pub fn matching_targets(&self, impacted: &[String]) -> Vec<(&str, &TargetConf)> {
+        let Some(ref targets) = self.test.targets else {
+            return Vec::new();
+        };
+        targets
+            .iter()
+            .filter(|(key, _)| {
+                impacted
+                    .iter()
+                    .any(|t| t == *key || Path::new(t).ends_with(key.as_str()))
+            })
+            .map(|(key, target)| (key.as_str(), target))
+            .collect()
+    }
+
+    /// The `[test.targets]` entry that applies to a single impacted target, if any.
+    pub fn target_conf(&self, target: &str) -> Option<(&str, &TargetConf)> {
+        self.matching_targets(&[target.to_string()])
+            .into_iter()
+            .next()
+    }
+
+    /// Chance that one impacted target fails on its own: an independent chance for every
+    /// matching `[test.targets]` entry.
+    pub fn target_flake_rate(&self, target: &str) -> f32 {
+        let pass = self
+            .matching_targets(&[target.to_string()])
+            .iter()
+            .fold(1.0, |pass, (_, target)| pass * (1.0 - target.flake_rate));
+        1.0 - pass
+    }
+
+    /// Probability that a test run over `impacted` fails: the global `flake_rate` plus every
+    /// impacted target's `target_flake_rate`.
+    pub fn failure_probability(&self, impacted: &[String]) -> f32 {
+        let pass = impacted
+            .iter()
+            .fold(1.0 - self.test.flake_rate, |pass, target| {
+                pass * (1.0 - self.target_flake_rate(target))
+            });
+        1.0 - pass
+    }
+
+    /// Sample how long one impacted target's suite takes in a run over `impacted_count` targets:
+    /// the longest of `base` (the run's `sleep_for` sample) and the `sleep_for` of every matching
+    /// `[test.targets]` entry, stretched by `sleep_scale_per_target` for every impacted target
+    /// beyond the first.
+    pub fn sample_target_duration<R: rand::Rng + ?Sized>(
+        &self,
+        target: &str,
+        impacted_count: usize,
+        base: std::time::Duration,
+        rng: &mut R,
+    ) -> std::time::Duration {
+        let mut longest = base;
+        for (_, target) in self.matching_targets(&[target.to_string()]) {
+            if let Some(ref sleep_for) = target.sleep_for {
+                let d = DurationDistribution::parse(sleep_for)
+                    .expect("Failed to parse target sleep_for into a duration distribution")
+                    .sample(rng);
+                longest = longest.max(d);
+            }
+        }
+
+        let extra_targets = impacted_count.saturating_sub(1) as f32;
+        longest.mul_f32(1.0 + self.test.sleep_scale_per_target * extra_targets)
+    }
+
+    /// Sample a test duration for `impacted`: the longest `sample_target_duration` of its
+    /// targets, which run in parallel.
+    pub fn sample_test_duration<R: rand::Rng + ?Sized>(
+        &self,
+        impacted: &[String],
+        rng: &mut R,
+    ) -> std::time::Duration {
+        let base = self.sleep_distribution().sample(rng);
+        impacted
+            .iter()
+            .map(|target| self.sample_target_duration(target, impacted.len(), base, rng))
+            .max()
+            .unwrap_or(base)
+    }
+
+    /// Apply the `[test.branches.<base_branch>]` profile, if one is configured.
+    /// Returns whether a profile was applied.
+    pub fn apply_branch_profile(&mut self, base_branch: &str) -> bool {
+        let Some(profile) = self
+            .test
+            .branches
+            .as_ref()
+            .and_then(|branches| branches.get(base_branch))
+            .cloned()
+        else {
+            return false;
+        };
+
+        if let Some(flake_rate) = profile.flake_rate {
+            self.test.flake_rate = flake_rate;
+        }
+        if let Some(sleep_for) = profile.sleep_for {
+            self.test.sleep_for = sleep_for;
+        }
+        if let Some(scale) = profile.sleep_scale_per_target {
+            self.test.sleep_scale_per_target = scale;
+        }
+        if profile.timeout.is_some() {
+            self.test.timeout = profile.timeout;
+        }
+        if let Some(hang_rate) = profile.hang_rate {
+            self.test.hang_rate = hang_rate;
+        }
+        if let Some(retry_pass_rate) = profile.retry_pass_rate {
+            self.test.retry_pass_rate = retry_pass_rate;
+        }
+        if let Some(targets) = profile.targets {
+            self.test
+                .targets
+                .get_or_insert_with(BTreeMap::new)
+                .extend(targets);
+        }
+        true
+    }
+
+    /// The CI timeout for a test run (`test.timeout`, or 6 hours when unset).
+    pub fn test_timeout(&self) -> std::time::Duration {
+        match self.test.timeout {
+            Some(ref timeout) => parse(timeout).expect("Failed to parse timeout into a Duration"),
+            None => std::time::Duration::from_secs(6 * 60 * 60),
+        }
+    }
+
+    /// Whether `now` falls inside a simulated outage window (see `test.outage_every`).
+    pub fn outage_active(&self, now: std::time::SystemTime) -> bool {
+        let Some(ref every) = self.test.outage_every else {
+            return false;
+        };
+        let every = parse(every).expect("Failed to parse outage_every into a Duration");
+        let duration = parse(&self.test.outage_duration)
+            .expect("Failed to parse outage_duration into a Duration");
+        let now_secs = now
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        crate::flake::in_outage(now_secs, every.as_secs(), duration.as_secs())
     }
 
     pub fn is_generator_disabled(&self) -> bool {
@@ -164,6 +572,74 @@ impl Conf {
             .expect("Failed to parse close_stale_after into a Duration")
     }
 
+    pub fn mergeable_poll_timeout_duration(&self) -> std::time::Duration {
+        parse(&self.pullrequest.mergeable_poll_timeout)
+            .expect("Failed to parse mergeable_poll_timeout into a Duration")
+    }
+
+    pub fn mergeable_poll_interval_duration(&self) -> std::time::Duration {
+        parse(&self.pullrequest.mergeable_poll_interval)
+            .expect("Failed to parse mergeable_poll_interval into a Duration")
+    }
+
+    /// Delay before mergeability poll number `attempt` (0-based): the configured interval
+    /// doubled per attempt, capped at one minute.
+    pub fn mergeable_backoff(&self, attempt: u32) -> std::time::Duration {
+        const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(60);
+        self.mergeable_poll_interval_duration()
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(MAX_BACKOFF)
+            .min(MAX_BACKOFF)
+    }
+
+    pub fn run_timeout_duration(&self) -> std::time::Duration {
+        parse(&self.merge.run_timeout).expect("Failed to parse run_timeout into a Duration")
+    }
+
+    pub fn run_retry_delay_duration(&self) -> std::time::Duration {
+        parse(&self.merge.run_retry_delay).expect("Failed to parse run_retry_delay into a Duration")
+    }
+
+    /// Sample how long to wait before enqueuing a new PR; None when `enqueue_delay` is unset.
+    pub fn sample_enqueue_delay<R: rand::Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Option<std::time::Duration> {
+        self.pullrequest.enqueue_delay.as_ref().map(|delay| {
+            DurationDistribution::parse(delay)
+                .expect("Failed to parse enqueue_delay into a duration distribution")
+                .sample(rng)
+        })
+    }
+
+    pub fn enqueue_checks_timeout_duration(&self) -> std::time::Duration {
+        parse(&self.pullrequest.enqueue_checks_timeout)
+            .expect("Failed to parse enqueue_checks_timeout into a Duration")
+    }
+
+    pub fn stack_enqueue_interval(&self) -> std::time::Duration {
+        parse(&self.stack.enqueue_interval)
+            .expect("Failed to parse stack.enqueue_interval into a Duration")
+    }
+
+    pub fn update_after_duration(&self) -> std::time::Duration {
+        parse(&self.pullrequest.update_after).expect("Failed to parse update_after into a Duration")
+    }
+
+    pub fn cancel_after_duration(&self) -> std::time::Duration {
+        parse(&self.pullrequest.cancel_after).expect("Failed to parse cancel_after into a Duration")
+    }
+
+    /// The priority to enqueue a generated PR with: one of `priorities` for a `priority_rate`
+    /// fraction of PRs, otherwise None (the queue's default).
+    pub fn pick_priority<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> Option<String> {
+        let priorities = &self.pullrequest.priorities;
+        if priorities.is_empty() || !rng.gen_bool(self.pullrequest.priority_rate as f64) {
+            return None;
+        }
+        Some(priorities[rng.gen_range(0..priorities.len())].clone())
+    }
+
     pub fn run_generate_for_duration(&self) -> std::time::Duration {
         parse(&self.pullrequest.run_generate_for)
             .expect("Failed to parse run_generate_for into a Duration")
@@ -285,7 +761,7 @@ impl Conf {
             .filter_map(|(prob, count_str)| count_str.parse::<usize>().ok().map(|d| (*prob, d)))
             .filter(|(_, d)| *d >= 1)
             .collect();
-        depths.sort_by(|a, b| b.1.cmp(&a.1));
+        depths.sort_by_key(|d| std::cmp::Reverse(d.1));
 
         for (prob, depth) in &depths {
             if *depth <= 1 {
@@ -447,8 +923,168 @@ impl Conf {
             return Err("flake_rate must be between 0.0 and 1.0");
         }
 
-        if parse(&self.test.sleep_for).is_err() {
-            return Err("sleep_for must be a valid duration string");
+        if DurationDistribution::parse(&self.test.sleep_for).is_err() {
+            return Err("sleep_for must be a valid duration or distribution (e.g. \"1 second\", \"uniform(30s, 90s)\")");
+        }
+
+        if self.test.sleep_scale_per_target < 0.0 {
+            return Err("sleep_scale_per_target must not be negative");
+        }
+
+        if let Some(ref targets) = self.test.targets {
+            for target in targets.values() {
+                if !(0.0..=1.0).contains(&target.flake_rate) {
+                    return Err("flake_rate in test.targets must be between 0.0 and 1.0");
+                }
+                if let Some(ref sleep_for) = target.sleep_for {
+                    if DurationDistribution::parse(sleep_for).is_err() {
+                        return Err(
+                            "sleep_for in test.targets must be a valid duration or distribution",
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(ref branches) = self.test.branches {
+            for profile in branches.values() {
+                let rates = [
+                    profile.flake_rate,
+                    profile.hang_rate,
+                    profile.retry_pass_rate,
+                ];
+                if rates.iter().flatten().any(|r| !(0.0..=1.0).contains(r)) {
+                    return Err("rates in test.branches must be between 0.0 and 1.0");
+                }
+                if profile.sleep_scale_per_target.is_some_and(|s| s < 0.0) {
+                    return Err("sleep_scale_per_target in test.branches must not be negative");
+                }
+                if let Some(ref sleep_for) = profile.sleep_for {
+                    if DurationDistribution::parse(sleep_for).is_err() {
+                        return Err(
+                            "sleep_for in test.branches must be a valid duration or distribution",
+                        );
+                    }
+                }
+                if let Some(ref timeout) = profile.timeout {
+                    if parse(timeout).is_err() {
+                        return Err("timeout in test.branches must be a valid duration string");
+                    }
+                }
+                for target in profile.targets.iter().flat_map(|t| t.values()) {
+                    if !(0.0..=1.0).contains(&target.flake_rate) {
+                        return Err(
+                            "flake_rate in test.branches targets must be between 0.0 and 1.0",
+                        );
+                    }
+                    if let Some(ref sleep_for) = target.sleep_for {
+                        if DurationDistribution::parse(sleep_for).is_err() {
+                            return Err("sleep_for in test.branches targets must be a valid duration or distribution");
+                        }
+                    }
+                }
+            }
+        }
+
+        if self
+            .test
+            .merge_branch_patterns
+            .iter()
+            .any(|p| regex::Regex::new(p).is_err())
+        {
+            return Err("merge_branch_patterns must be valid regular expressions");
+        }
+
+        if let Some(ref timeout) = self.test.timeout {
+            if parse(timeout).is_err() {
+                return Err("timeout must be a valid duration string");
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.test.hang_rate) {
+            return Err("hang_rate must be between 0.0 and 1.0");
+        }
+
+        if !(0.0..=1.0).contains(&self.test.retry_pass_rate) {
+            return Err("retry_pass_rate must be between 0.0 and 1.0");
+        }
+
+        if let Some(ref outage_every) = self.test.outage_every {
+            let every =
+                parse(outage_every).map_err(|_| "outage_every must be a valid duration string")?;
+            let duration = parse(&self.test.outage_duration)
+                .map_err(|_| "outage_duration must be a valid duration string")?;
+            if duration > every {
+                return Err("outage_duration cannot be longer than outage_every");
+            }
+            if !(0.0..=1.0).contains(&self.test.outage_flake_rate) {
+                return Err("outage_flake_rate must be between 0.0 and 1.0");
+            }
+        }
+
+        if self.pullrequest.pairwise_conflict_every == 1 {
+            return Err("pairwise_conflict_every must be 0 (disabled) or at least 2");
+        }
+
+        if !(0.0..=1.0).contains(&self.pullrequest.priority_rate) {
+            return Err("priority_rate must be between 0.0 and 1.0");
+        }
+
+        if !self
+            .pullrequest
+            .priorities
+            .iter()
+            .all(|p| crate::trunk::is_valid_priority(p))
+        {
+            return Err("priorities must be urgent, high, medium, low or a number from 0 to 255");
+        }
+
+        if !(0.0..=1.0).contains(&self.pullrequest.cancel_rate) {
+            return Err("cancel_rate must be between 0.0 and 1.0");
+        }
+
+        if parse(&self.pullrequest.mergeable_poll_timeout).is_err() {
+            return Err("mergeable_poll_timeout must be a valid duration string");
+        }
+
+        if parse(&self.pullrequest.mergeable_poll_interval).is_err() {
+            return Err("mergeable_poll_interval must be a valid duration string");
+        }
+
+        if self.pullrequest.cancel_rate > 0.0 && parse(&self.pullrequest.cancel_after).is_err() {
+            return Err("cancel_after must be a valid duration string");
+        }
+
+        if !(0.0..=1.0).contains(&self.pullrequest.update_rate) {
+            return Err("update_rate must be between 0.0 and 1.0");
+        }
+
+        if !(0.0..=1.0).contains(&self.pullrequest.update_force_push_rate) {
+            return Err("update_force_push_rate must be between 0.0 and 1.0");
+        }
+
+        if self.pullrequest.update_rate > 0.0 && parse(&self.pullrequest.update_after).is_err() {
+            return Err("update_after must be a valid duration string");
+        }
+
+        if let Some(ref delay) = self.pullrequest.enqueue_delay {
+            if DurationDistribution::parse(delay).is_err() {
+                return Err("enqueue_delay must be a valid duration or distribution");
+            }
+        }
+
+        if self.pullrequest.enqueue_wait_for_checks
+            && parse(&self.pullrequest.enqueue_checks_timeout).is_err()
+        {
+            return Err("enqueue_checks_timeout must be a valid duration string");
+        }
+
+        if matches!(
+            self.stack.enqueue,
+            StackEnqueue::BottomUp | StackEnqueue::RandomOrder
+        ) && parse(&self.stack.enqueue_interval).is_err()
+        {
+            return Err("stack.enqueue_interval must be a valid duration string");
         }
 
         if self.pullrequest.requests_per_hour > 0 && self.pullrequest.requests_per_run > 0 {
@@ -481,11 +1117,31 @@ impl Conf {
                 if self.merge.run.is_empty() {
                     return Err("merge trigger is set to 'run' but no run command is provided");
                 }
+                if self.merge.run_shell && matches!(self.merge.run, RunCommand::Argv(_)) {
+                    return Err("run_shell needs run to be a string, not an argv array");
+                }
+                if parse(&self.merge.run_timeout).is_err() {
+                    return Err("run_timeout must be a valid duration string");
+                }
+                if parse(&self.merge.run_retry_delay).is_err() {
+                    return Err("run_retry_delay must be a valid duration string");
+                }
             }
             EnqueueTrigger::Api => {
                 // TRUNK_TOKEN validation is done at runtime when actually needed
                 // No need to validate here during config loading
             }
+            EnqueueTrigger::Github => {
+                // GitHub token and merge queue availability are checked when enqueuing
+            }
+            EnqueueTrigger::Mergify | EnqueueTrigger::Bors => {
+                // The enqueue comment is fixed by the queue
+            }
+            EnqueueTrigger::Graphite => {
+                if self.merge.labels.is_empty() {
+                    return Err("merge trigger is set to 'graphite' but no labels are provided");
+                }
+            }
         }
 
         Ok(())
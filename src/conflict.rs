@@ -0,0 +1,135 @@
+//! Pairwise logical conflicts: two PRs that each pass on their own but fail together.
+//!
+//! The first PR of a pair writes `<dir>/<pair>.a` and the next PR from another stack writes
+//! `<dir>/<pair>.b`, where `<pair>` is the number GitHub assigned the first PR. PRs stacked on a
+//! side inherit its marker, so the `b` side is never a stack child of the `a` side. Neither
+//! marker fails a test by itself; `test-sim` fails when both markers of a pair are present in the
+//! tested tree, which only happens once both PRs are merged or tested together.
+
+use std::path::{Path, PathBuf};
+
+/// Hands out pair sides to PRs in creation order.
+#[derive(Debug, Default)]
+pub struct PairPlanner {
+    /// Pair id and stack of the `a` side still waiting for its `b` side
+    open: Option<(u32, String)>,
+}
+
+impl PairPlanner {
+    /// Which side of a pair (if any) the PR about to be created as `pr_number` in stack
+    /// `stack_id` should write, as `(pair_id, side)`. Every `every`th PR opens a pair (side `a`)
+    /// and the next PR outside the `a` side's stack closes it (side `b`). Nothing changes until
+    /// the PR is `created`.
+    pub fn plan(&self, pr_number: u32, stack_id: &str, every: u32) -> Option<(u32, char)> {
+        if let Some((pair_id, ref a_stack)) = self.open {
+            if a_stack == stack_id {
+                // Stacked on the `a` side: it already carries that marker
+                return None;
+            }
+            return Some((pair_id, 'b'));
+        }
+        if every < 2 || pr_number == 0 || !pr_number.is_multiple_of(every) {
+            return None;
+        }
+        Some((pr_number, 'a'))
+    }
+
+    /// Record the side a PR in stack `stack_id` was created with, keyed by the pair id its
+    /// marker was written under.
+    pub fn created(&mut self, pair: Option<(u32, char)>, stack_id: &str) {
+        match pair {
+            Some((pair_id, 'a')) => self.open = Some((pair_id, stack_id.to_string())),
+            Some((_, 'b')) => self.open = None,
+            _ => {}
+        }
+    }
+}
+
+pub fn pair_marker(dir: &str, pair_id: u32, side: char) -> PathBuf {
+    Path::new(dir).join(format!("{}.{}", pair_id, side))
+}
+
+/// Pair ids whose `a` and `b` markers are both present in `dir`, sorted ascending.
+pub fn conflicting_pairs(dir: &str) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+
+    let mut pairs: Vec<u32> = names
+        .iter()
+        .filter_map(|name| name.strip_suffix(".a"))
+        .filter_map(|id| id.parse::<u32>().ok())
+        .filter(|id| names.contains(&format!("{}.b", id)))
+        .collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Plan a side for the next PR and record it as created under `number`.
+    fn create(planner: &mut PairPlanner, number: u32, stack: &str) -> Option<(u32, char)> {
+        let pair = planner.plan(number, stack, 5);
+        planner.created(pair, stack);
+        pair
+    }
+
+    #[test]
+    fn test_pair_planner() {
+        let mut planner = PairPlanner::default();
+        assert_eq!(create(&mut planner, 9, "s1"), None);
+        assert_eq!(create(&mut planner, 10, "s2"), Some((10, 'a')));
+        assert_eq!(create(&mut planner, 11, "s3"), Some((10, 'b')));
+        assert_eq!(create(&mut planner, 12, "s4"), None);
+
+        // Never closed by a PR stacked on the `a` side
+        assert_eq!(create(&mut planner, 15, "s5"), Some((15, 'a')));
+        assert_eq!(create(&mut planner, 16, "s5"), None);
+        assert_eq!(create(&mut planner, 17, "s5"), None);
+        assert_eq!(create(&mut planner, 18, "s6"), Some((15, 'b')));
+
+        // Disabled, and too frequent for a PR to belong to a single pair
+        let planner = PairPlanner::default();
+        assert_eq!(planner.plan(10, "s1", 0), None);
+        assert_eq!(planner.plan(10, "s1", 1), None);
+    }
+
+    #[test]
+    fn test_pair_planner_waits_for_created_prs() {
+        let mut planner = PairPlanner::default();
+        // The `b` side stays available when its PR could not be created
+        planner.created(planner.plan(10, "s1", 5), "s1");
+        assert_eq!(planner.plan(11, "s2", 5), Some((10, 'b')));
+        assert_eq!(planner.plan(12, "s3", 5), Some((10, 'b')));
+
+        // Keyed by the number GitHub assigned, not the predicted one
+        let mut planner = PairPlanner::default();
+        assert_eq!(planner.plan(20, "s1", 5), Some((20, 'a')));
+        planner.created(Some((23, 'a')), "s1");
+        assert_eq!(planner.plan(24, "s2", 5), Some((23, 'b')));
+    }
+
+    #[test]
+    fn test_conflicting_pairs_requires_both_sides() {
+        let dir = std::env::temp_dir().join(format!("mq_conflict_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir_str = dir.to_str().unwrap();
+
+        fs::write(pair_marker(dir_str, 10, 'a'), "").unwrap();
+        fs::write(pair_marker(dir_str, 20, 'b'), "").unwrap();
+        assert!(conflicting_pairs(dir_str).is_empty());
+
+        fs::write(pair_marker(dir_str, 10, 'b'), "").unwrap();
+        assert_eq!(conflicting_pairs(dir_str), vec![10]);
+
+        let _ = fs::remove_dir_all(&dir);
+        assert!(conflicting_pairs(dir_str).is_empty());
+    }
+}
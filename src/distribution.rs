@@ -0,0 +1,316 @@
+use parse_duration::parse;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Longest duration a sample can take. Far tails of wide distributions are clamped to it
+/// instead of overflowing `Duration`.
+pub const MAX_SAMPLE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A test duration, either fixed or drawn from a distribution.
+///
+/// Parsed from `sleep_for` style strings:
+///   "90 seconds"                 fixed duration
+///   "uniform(30s, 90s)"          uniform between the two bounds
+///   "normal(60s, 10s)"           normal with mean and standard deviation (clamped at 0)
+///   "lognormal(60s, 0.5)"        log-normal with median and sigma (long right tail)
+///   "empirical(durations.txt)"   samples from a file of observed durations, one per line,
+///                                optionally weighted as `<duration>,<weight>`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DurationDistribution {
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    Normal { mean: Duration, std_dev: Duration },
+    LogNormal { median: Duration, sigma: f64 },
+    Empirical(Vec<(Duration, f64)>),
+}
+
+/// Weighted samples of an empirical distribution.
+type Samples = Vec<(Duration, f64)>;
+
+impl DurationDistribution {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+        let Some((name, rest)) = spec.split_once('(') else {
+            return parse_duration_arg(spec).map(DurationDistribution::Fixed);
+        };
+        let args_str = rest
+            .strip_suffix(')')
+            .ok_or_else(|| format!("missing closing ')' in '{}'", spec))?;
+        let args: Vec<&str> = args_str.split(',').map(|a| a.trim()).collect();
+
+        let expect_args = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} expects {} argument(s), got {}",
+                    name.trim(),
+                    n,
+                    args.len()
+                ))
+            }
+        };
+
+        match name.trim().to_lowercase().as_str() {
+            "fixed" => {
+                expect_args(1)?;
+                Ok(DurationDistribution::Fixed(parse_duration_arg(args[0])?))
+            }
+            "uniform" => {
+                expect_args(2)?;
+                let low = parse_duration_arg(args[0])?;
+                let high = parse_duration_arg(args[1])?;
+                if low > high {
+                    return Err(format!(
+                        "uniform lower bound exceeds upper bound in '{}'",
+                        spec
+                    ));
+                }
+                Ok(DurationDistribution::Uniform(low, high))
+            }
+            "normal" => {
+                expect_args(2)?;
+                Ok(DurationDistribution::Normal {
+                    mean: parse_duration_arg(args[0])?,
+                    std_dev: parse_duration_arg(args[1])?,
+                })
+            }
+            "lognormal" => {
+                expect_args(2)?;
+                let sigma = args[1]
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid lognormal sigma '{}'", args[1]))?;
+                if sigma < 0.0 {
+                    return Err("lognormal sigma must not be negative".to_string());
+                }
+                Ok(DurationDistribution::LogNormal {
+                    median: parse_duration_arg(args[0])?,
+                    sigma,
+                })
+            }
+            "empirical" => {
+                expect_args(1)?;
+                load_empirical(args[0]).map(DurationDistribution::Empirical)
+            }
+            other => Err(format!("unknown duration distribution '{}'", other)),
+        }
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Duration {
+        match self {
+            DurationDistribution::Fixed(d) => *d,
+            DurationDistribution::Uniform(low, high) => {
+                if low == high {
+                    *low
+                } else {
+                    clamp_secs(rng.gen_range(low.as_secs_f64()..=high.as_secs_f64()))
+                }
+            }
+            DurationDistribution::Normal { mean, std_dev } => {
+                let secs = mean.as_secs_f64() + std_dev.as_secs_f64() * standard_normal(rng);
+                clamp_secs(secs)
+            }
+            DurationDistribution::LogNormal { median, sigma } => {
+                clamp_secs(median.as_secs_f64() * (sigma * standard_normal(rng)).exp())
+            }
+            DurationDistribution::Empirical(samples) => {
+                let total: f64 = samples.iter().map(|(_, w)| w).sum();
+                let mut pick = rng.gen_range(0.0..total);
+                for (duration, weight) in samples {
+                    if pick < *weight {
+                        return *duration;
+                    }
+                    pick -= weight;
+                }
+                samples.last().map(|(d, _)| *d).unwrap_or_default()
+            }
+        }
+    }
+}
+
+/// Seconds to a `Duration` between zero and `MAX_SAMPLE` (NaN, e.g. 0 × ∞, becomes zero).
+fn clamp_secs(secs: f64) -> Duration {
+    if secs.is_nan() || secs <= 0.0 {
+        Duration::ZERO
+    } else {
+        Duration::from_secs_f64(secs.min(MAX_SAMPLE.as_secs_f64()))
+    }
+}
+
+/// Samples from an empirical duration file. Configs are parsed again every time a duration is
+/// sampled, so each file is read once per process and kept.
+fn load_empirical(path: &str) -> Result<Samples, String> {
+    static LOADED: OnceLock<Mutex<HashMap<String, Samples>>> = OnceLock::new();
+    let mut loaded = LOADED.get_or_init(Default::default).lock().unwrap();
+    if let Some(samples) = loaded.get(path) {
+        return Ok(samples.clone());
+    }
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("could not read '{}': {}", path, e))?;
+    let samples = parse_empirical(&content)?;
+    loaded.insert(path.to_string(), samples.clone());
+    Ok(samples)
+}
+
+fn parse_duration_arg(arg: &str) -> Result<Duration, String> {
+    parse(arg).map_err(|_| format!("invalid duration '{}'", arg))
+}
+
+/// Parse an empirical duration file: one `<duration>` or `<duration>,<weight>` per line.
+/// Blank lines and lines starting with `#` are ignored.
+fn parse_empirical(content: &str) -> Result<Vec<(Duration, f64)>, String> {
+    let mut samples = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (duration_str, weight) = match line.split_once(',') {
+            Some((d, w)) => {
+                let weight = w
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid weight in line '{}'", line))?;
+                (d, weight)
+            }
+            None => (line, 1.0),
+        };
+        if weight <= 0.0 {
+            return Err(format!("weight must be greater than 0 in line '{}'", line));
+        }
+        samples.push((parse_duration_arg(duration_str.trim())?, weight));
+    }
+    if samples.is_empty() {
+        return Err("empirical duration file contains no samples".to_string());
+    }
+    Ok(samples)
+}
+
+/// Standard normal variate via the Box-Muller transform.
+fn standard_normal<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen_range(0.0..1.0); // (0, 1], keeps ln() finite
+    let u2: f64 = rng.gen_range(0.0..1.0);
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_parse_plain_duration_is_fixed() {
+        assert_eq!(
+            DurationDistribution::parse("1 second"),
+            Ok(DurationDistribution::Fixed(Duration::from_secs(1)))
+        );
+        assert_eq!(
+            DurationDistribution::parse("fixed(2m)"),
+            Ok(DurationDistribution::Fixed(Duration::from_secs(120)))
+        );
+    }
+
+    #[test]
+    fn test_parse_distributions() {
+        assert_eq!(
+            DurationDistribution::parse("uniform(30s, 90s)"),
+            Ok(DurationDistribution::Uniform(
+                Duration::from_secs(30),
+                Duration::from_secs(90)
+            ))
+        );
+        assert_eq!(
+            DurationDistribution::parse("normal(60s, 10s)"),
+            Ok(DurationDistribution::Normal {
+                mean: Duration::from_secs(60),
+                std_dev: Duration::from_secs(10)
+            })
+        );
+        assert_eq!(
+            DurationDistribution::parse("lognormal(1 minute, 0.5)"),
+            Ok(DurationDistribution::LogNormal {
+                median: Duration::from_secs(60),
+                sigma: 0.5
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(DurationDistribution::parse("not a duration").is_err());
+        assert!(DurationDistribution::parse("uniform(90s, 30s)").is_err());
+        assert!(DurationDistribution::parse("uniform(30s)").is_err());
+        assert!(DurationDistribution::parse("normal(60s, 10s").is_err());
+        assert!(DurationDistribution::parse("gamma(60s, 2)").is_err());
+        assert!(DurationDistribution::parse("empirical(/does/not/exist.txt)").is_err());
+    }
+
+    #[test]
+    fn test_parse_empirical_lines() {
+        let samples = parse_empirical("# ci durations\n30s\n\n2m,3\n").unwrap();
+        assert_eq!(
+            samples,
+            vec![
+                (Duration::from_secs(30), 1.0),
+                (Duration::from_secs(120), 3.0)
+            ]
+        );
+        assert!(parse_empirical("# nothing\n").is_err());
+        assert!(parse_empirical("30s,0\n").is_err());
+    }
+
+    #[test]
+    fn test_samples_stay_in_range() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let uniform =
+            DurationDistribution::Uniform(Duration::from_secs(30), Duration::from_secs(90));
+        let normal = DurationDistribution::Normal {
+            mean: Duration::from_secs(1),
+            std_dev: Duration::from_secs(5),
+        };
+        let empirical = DurationDistribution::Empirical(vec![
+            (Duration::from_secs(10), 1.0),
+            (Duration::from_secs(20), 1.0),
+        ]);
+        for _ in 0..1000 {
+            let d = uniform.sample(&mut rng);
+            assert!(d >= Duration::from_secs(30) && d <= Duration::from_secs(90));
+            // Clamped at zero rather than panicking on a negative duration
+            normal.sample(&mut rng);
+            let e = empirical.sample(&mut rng);
+            assert!(e == Duration::from_secs(10) || e == Duration::from_secs(20));
+        }
+    }
+
+    #[test]
+    fn test_wide_distributions_are_clamped() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let lognormal = DurationDistribution::parse("lognormal(1 hour, 500)").unwrap();
+        let zero_median = DurationDistribution::LogNormal {
+            median: Duration::ZERO,
+            sigma: 1000.0,
+        };
+        for _ in 0..1000 {
+            assert!(lognormal.sample(&mut rng) <= MAX_SAMPLE);
+            assert!(zero_median.sample(&mut rng) <= MAX_SAMPLE);
+        }
+    }
+
+    #[test]
+    fn test_lognormal_median() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let lognormal = DurationDistribution::LogNormal {
+            median: Duration::from_secs(60),
+            sigma: 0.5,
+        };
+        let mut samples: Vec<f64> = (0..2001)
+            .map(|_| lognormal.sample(&mut rng).as_secs_f64())
+            .collect();
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = samples[1000];
+        assert!((50.0..70.0).contains(&median), "median was {}", median);
+    }
+}
@@ -0,0 +1,69 @@
+//! Flake models beyond an independent draw per run: draws pinned to a commit SHA and
+//! wall-clock "outage" windows shared by every concurrent test run.
+
+/// Deterministic draw in `[0, 1)` for a commit SHA, so every run of the same commit sees the
+/// same value. Uses FNV-1a, which (unlike `DefaultHasher`) is stable across builds.
+pub fn sticky_draw(sha: &str) -> f32 {
+    const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+    let hash = sha.trim().bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    });
+    // Top 24 bits give an exactly representable f32 in [0, 1)
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Whether `now_secs` (seconds since the Unix epoch) falls inside an outage window.
+///
+/// Time is split into periods of `every_secs`; each period contains one window of
+/// `duration_secs` whose start is pseudo-randomly placed within the period. Placement depends
+/// only on the period index, so independent processes agree on when outages happen.
+pub fn in_outage(now_secs: u64, every_secs: u64, duration_secs: u64) -> bool {
+    if every_secs == 0 || duration_secs == 0 {
+        return false;
+    }
+    if duration_secs >= every_secs {
+        return true;
+    }
+    let period = now_secs / every_secs;
+    let slack = every_secs - duration_secs;
+    let offset = (sticky_draw(&period.to_string()) as f64 * slack as f64) as u64;
+    let into_period = now_secs % every_secs;
+    into_period >= offset && into_period < offset + duration_secs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sticky_draw_is_stable_and_in_range() {
+        let sha = "3f2a9c1e8b7d6a5f4e3d2c1b0a9f8e7d6c5b4a39";
+        assert_eq!(sticky_draw(sha), sticky_draw(sha));
+        assert_ne!(sticky_draw(sha), sticky_draw("0000000"));
+        for i in 0..1000 {
+            let draw = sticky_draw(&format!("sha{}", i));
+            assert!((0.0..1.0).contains(&draw));
+        }
+    }
+
+    #[test]
+    fn test_one_outage_window_per_period() {
+        let every = 3600;
+        let duration = 300;
+        for period in 0..5u64 {
+            let start = period * every;
+            let active = (start..start + every)
+                .filter(|t| in_outage(*t, every, duration))
+                .count();
+            assert_eq!(active as u64, duration, "period {}", period);
+        }
+    }
+
+    #[test]
+    fn test_outage_disabled_or_always_on() {
+        assert!(!in_outage(1234, 0, 300));
+        assert!(!in_outage(1234, 3600, 0));
+        assert!(in_outage(1234, 300, 300));
+    }
+}
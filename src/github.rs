@@ -1,20 +1,496 @@
 use crate::process::try_gh;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 
 pub struct GitHub;
 
+/// GraphQL connection pages are capped at 100 nodes by GitHub.
+const PAGE_SIZE: u32 = 100;
+
+const LIST_PRS_QUERY: &str = r#"
+query($owner: String!, $name: String!, $states: [PullRequestState!], $labels: [String!], $after: String) {
+  repository(owner: $owner, name: $name) {
+    pullRequests(first: PAGE_SIZE, after: $after, states: $states, labels: $labels, orderBy: {field: CREATED_AT, direction: ASC}) {
+      pageInfo { hasNextPage endCursor }
+      nodes {
+        number
+        mergeable
+        headRefName
+        baseRefName
+        comments(last: 100) { nodes { body createdAt } }
+      }
+    }
+  }
+}
+"#;
+
+const LAST_PR_QUERY: &str = r#"
+query($owner: String!, $name: String!) {
+  repository(owner: $owner, name: $name) {
+    pullRequests(first: 1, orderBy: {field: CREATED_AT, direction: DESC}) {
+      nodes { number }
+    }
+  }
+}
+"#;
+
+const ENQUEUE_PR_MUTATION: &str = r#"
+mutation($id: ID!) {
+  enqueuePullRequest(input: {pullRequestId: $id}) {
+    mergeQueueEntry { position state }
+  }
+}
+"#;
+
+const AUTO_MERGE_MUTATION: &str = r#"
+mutation($id: ID!) {
+  enablePullRequestAutoMerge(input: {pullRequestId: $id}) {
+    pullRequest { autoMergeRequest { enabledAt } }
+  }
+}
+"#;
+
+const DEQUEUE_PR_MUTATION: &str = r#"
+mutation($id: ID!) {
+  dequeuePullRequest(input: {id: $id}) {
+    mergeQueueEntry { state }
+  }
+}
+"#;
+
+const REQUIRED_CHECKS_QUERY: &str = r#"
+query($owner: String!, $name: String!, $number: Int!) {
+  repository(owner: $owner, name: $name) {
+    pullRequest(number: $number) {
+      commits(last: 1) {
+        nodes {
+          commit {
+            statusCheckRollup {
+              contexts(first: 100) {
+                nodes {
+                  __typename
+                  ... on CheckRun { name status isRequired(pullRequestNumber: $number) }
+                  ... on StatusContext { context state isRequired(pullRequestNumber: $number) }
+                }
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+/// Stacks deeper than this are assumed to be a cycle in the base branches.
+const MAX_STACK_DEPTH: usize = 50;
+
+/// Filters applied by GitHub when listing pull requests with `GitHub::list_prs`.
+#[derive(Debug, Default, Clone)]
+pub struct PrQuery {
+    /// `PullRequestState` values (`OPEN`, `CLOSED`, `MERGED`). Empty means every state.
+    pub states: Vec<String>,
+    /// Only PRs carrying at least one of these labels. Empty means no label filter.
+    pub labels: Vec<String>,
+    /// Only PRs whose head branch starts with this prefix (e.g. `stack-change/`). Unlike the
+    /// other filters this one is applied client-side: every PR matching them is still downloaded.
+    pub head_prefix: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PrSummary {
+    pub number: u32,
+    /// `MERGEABLE`, `CONFLICTING` or `UNKNOWN`
+    #[serde(default)]
+    pub mergeable: String,
+    #[serde(default)]
+    pub head_ref_name: String,
+    #[serde(default)]
+    pub base_ref_name: String,
+    #[serde(default, deserialize_with = "from_nodes")]
+    pub comments: Vec<PrComment>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PrComment {
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub created_at: String,
+}
+
+/// Unwrap a GraphQL `{ nodes: [...] }` connection into its nodes.
+fn from_nodes<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    struct Nodes<T> {
+        nodes: Vec<T>,
+    }
+    Ok(Nodes::deserialize(deserializer)?.nodes)
+}
+
+/// Parse one page of `LIST_PRS_QUERY` output.
+/// Returns the PRs on the page and the cursor for the next page, if there is one.
+pub fn parse_pr_page(json: &str) -> Result<(Vec<PrSummary>, Option<String>), String> {
+    let v: Value = serde_json::from_str(json).map_err(|e| format!("invalid JSON: {}", e))?;
+    if let Some(errors) = v.get("errors") {
+        return Err(format!("GraphQL errors: {}", errors));
+    }
+
+    let connection = &v["data"]["repository"]["pullRequests"];
+    let prs: Vec<PrSummary> = serde_json::from_value(connection["nodes"].clone())
+        .map_err(|e| format!("unexpected pullRequests shape: {}", e))?;
+
+    let page_info = &connection["pageInfo"];
+    let next_cursor = if page_info["hasNextPage"].as_bool().unwrap_or(false) {
+        page_info["endCursor"].as_str().map(|s| s.to_string())
+    } else {
+        None
+    };
+
+    Ok((prs, next_cursor))
+}
+
+/// A PR's place in GitHub's merge queue after `enqueuePullRequest`.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct MergeQueueEntry {
+    pub position: Option<u32>,
+    /// `QUEUED`, `AWAITING_CHECKS`, `MERGEABLE`, `UNMERGEABLE` or `LOCKED`
+    pub state: String,
+}
+
+/// Parse the response of `REQUIRED_CHECKS_QUERY` into the names of required checks that are
+/// still queued or running. A commit without checks has none pending.
+pub fn parse_pending_required_checks(json: &str) -> Result<Vec<String>, String> {
+    let v: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    if let Some(errors) = v.get("errors") {
+        return Err(graphql_error_messages(errors));
+    }
+    let commits = v["data"]["repository"]["pullRequest"]["commits"]["nodes"]
+        .as_array()
+        .ok_or("response has no pull request commits")?;
+    let contexts = commits
+        .last()
+        .and_then(|c| c["commit"]["statusCheckRollup"]["contexts"]["nodes"].as_array());
+    Ok(contexts
+        .into_iter()
+        .flatten()
+        .filter(|c| c["isRequired"].as_bool() == Some(true))
+        .filter(|c| match c["__typename"].as_str() {
+            Some("CheckRun") => c["status"].as_str() != Some("COMPLETED"),
+            _ => matches!(c["state"].as_str(), Some("PENDING") | Some("EXPECTED")),
+        })
+        .map(|c| {
+            c["name"]
+                .as_str()
+                .or_else(|| c["context"].as_str())
+                .unwrap_or("unknown")
+                .to_string()
+        })
+        .collect())
+}
+
+/// Parse the `enqueuePullRequest` mutation response.
+pub fn parse_enqueue_response(json: &str) -> Result<MergeQueueEntry, String> {
+    let v: Value = serde_json::from_str(json).map_err(|e| format!("invalid JSON: {}", e))?;
+    if let Some(errors) = v.get("errors") {
+        return Err(graphql_error_messages(errors));
+    }
+    serde_json::from_value(v["data"]["enqueuePullRequest"]["mergeQueueEntry"].clone())
+        .map_err(|e| format!("unexpected enqueuePullRequest shape: {}", e))
+}
+
+/// The `message` of every GraphQL error, joined; the raw errors if none have one.
+fn graphql_error_messages(errors: &Value) -> String {
+    let messages: Vec<&str> = errors
+        .as_array()
+        .map(|a| a.iter().filter_map(|e| e["message"].as_str()).collect())
+        .unwrap_or_default();
+    if messages.is_empty() {
+        format!("GraphQL errors: {}", errors)
+    } else {
+        messages.join("; ")
+    }
+}
+
 impl GitHub {
     pub fn comment(pr: &str, body: &str, token: &str) -> String {
         try_gh(&["pr", "comment", pr, "--body", body], token).expect("Failed to comment on PR")
     }
 
+    pub fn try_comment(pr: &str, body: &str, token: &str) -> Result<String, String> {
+        try_gh(&["pr", "comment", pr, "--body", body], token)
+    }
+
     pub fn close(pr: &str, token: &str) -> String {
         try_gh(&["pr", "close", pr], token).expect("Failed to close PR")
     }
 
-    pub fn add_label(pr: &str, label: &str, token: &str) -> String {
-        try_gh(&["pr", "edit", pr, "--add-label", label], token).expect("Failed to add label to PR")
+    pub fn try_close(pr: &str, token: &str) -> Result<String, String> {
+        try_gh(&["pr", "close", pr], token)
+    }
+
+    pub fn edit_title(pr: &str, title: &str, token: &str) -> Result<String, String> {
+        try_gh(&["pr", "edit", pr, "--title", title], token)
+    }
+
+    pub fn edit_body(pr: &str, body: &str, token: &str) -> Result<String, String> {
+        try_gh(&["pr", "edit", pr, "--body", body], token)
+    }
+
+    /// Add all of `labels` to a PR in a single edit.
+    pub fn add_labels(pr: &str, labels: &[String], token: &str) -> Result<String, String> {
+        let mut args = vec!["pr", "edit", pr];
+        for label in labels {
+            args.extend(["--add-label", label.as_str()]);
+        }
+        try_gh(&args, token)
+    }
+
+    /// List every pull request matching `query`, following GraphQL cursors until the last page.
+    ///
+    /// State and label filters are evaluated by GitHub. The `pullRequests` connection only
+    /// supports exact head branch matches, so `head_prefix` is applied to each page as it arrives.
+    /// Each PR carries its newest 100 comments, which is where the enqueue comments are.
+    pub fn list_prs(query: &PrQuery, token: &str) -> Result<Vec<PrSummary>, String> {
+        let graphql = LIST_PRS_QUERY.replace("PAGE_SIZE", &PAGE_SIZE.to_string());
+        let query_arg = format!("query={}", graphql);
+        let state_args: Vec<String> = query
+            .states
+            .iter()
+            .map(|s| format!("states[]={}", s))
+            .collect();
+        let label_args: Vec<String> = query
+            .labels
+            .iter()
+            .map(|l| format!("labels[]={}", l))
+            .collect();
+
+        let mut prs = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut args: Vec<&str> = vec![
+                "api",
+                "graphql",
+                "-F",
+                "owner={owner}",
+                "-F",
+                "name={repo}",
+                "-f",
+                &query_arg,
+            ];
+            for arg in state_args.iter().chain(label_args.iter()) {
+                args.push("-f");
+                args.push(arg);
+            }
+            let after_arg = cursor.as_ref().map(|c| format!("after={}", c));
+            if let Some(ref after) = after_arg {
+                args.push("-f");
+                args.push(after);
+            }
+
+            let json_str = try_gh(&args, token)?;
+            let (page, next_cursor) = parse_pr_page(&json_str)?;
+            prs.extend(page.into_iter().filter(|pr| {
+                query
+                    .head_prefix
+                    .as_deref()
+                    .is_none_or(|prefix| pr.head_ref_name.starts_with(prefix))
+            }));
+
+            match next_cursor {
+                Some(c) => cursor = Some(c),
+                None => return Ok(prs),
+            }
+        }
+    }
+
+    /// Number of the most recently created pull request in any state, or 0 if there are none.
+    pub fn get_last_pr_number(token: &str) -> Result<u32, String> {
+        let query_arg = format!("query={}", LAST_PR_QUERY);
+        let json_str = try_gh(
+            &[
+                "api",
+                "graphql",
+                "-F",
+                "owner={owner}",
+                "-F",
+                "name={repo}",
+                "-f",
+                &query_arg,
+            ],
+            token,
+        )?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        Ok(
+            v["data"]["repository"]["pullRequests"]["nodes"][0]["number"]
+                .as_u64()
+                .unwrap_or(0) as u32,
+        )
+    }
+
+    /// Current `mergeable` state of a single PR (`MERGEABLE`, `CONFLICTING` or `UNKNOWN`).
+    pub fn get_pr_mergeable(pr: &str, token: &str) -> Result<String, String> {
+        let json_str = try_gh(&["pr", "view", pr, "--json", "mergeable"], token)?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        v["mergeable"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| format!("PR {} JSON does not contain 'mergeable' field", pr))
+    }
+
+    pub fn get_pr_body(pr: &str, token: &str) -> Result<String, String> {
+        let json_str = try_gh(&["pr", "view", pr, "--json", "body"], token)?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        Ok(v["body"].as_str().unwrap_or("").to_string())
+    }
+
+    pub fn get_pr_head_branch(pr: &str, token: &str) -> Result<String, String> {
+        let json_str = try_gh(&["pr", "view", pr, "--json", "headRefName"], token)?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        v["headRefName"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| format!("PR {} JSON does not contain 'headRefName'", pr))
+    }
+
+    /// GraphQL node id of a PR, as the mutations below expect.
+    pub fn get_pr_node_id(pr: &str, token: &str) -> Result<String, String> {
+        let json_str = try_gh(&["pr", "view", pr, "--json", "id"], token)?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        v["id"]
+            .as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| format!("PR {} JSON does not contain 'id' field", pr))
+    }
+
+    /// Add a PR to its base branch's GitHub merge queue.
+    pub fn enqueue_pr(pr: &str, token: &str) -> Result<MergeQueueEntry, String> {
+        let json_str = Self::run_pr_mutation(ENQUEUE_PR_MUTATION, pr, token)?;
+        parse_enqueue_response(&json_str)
+    }
+
+    /// Enable auto-merge, which on a merge-queue branch queues the PR once its checks pass.
+    pub fn enable_auto_merge(pr: &str, token: &str) -> Result<(), String> {
+        let json_str = Self::run_pr_mutation(AUTO_MERGE_MUTATION, pr, token)?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        match v.get("errors") {
+            Some(errors) => Err(graphql_error_messages(errors)),
+            None => Ok(()),
+        }
+    }
+
+    /// Names of the PR's required checks that have not finished yet.
+    pub fn pending_required_checks(pr: &str, token: &str) -> Result<Vec<String>, String> {
+        let query_arg = format!("query={}", REQUIRED_CHECKS_QUERY);
+        let number_arg = format!("number={}", pr);
+        let json_str = try_gh(
+            &[
+                "api",
+                "graphql",
+                "-f",
+                &query_arg,
+                "-F",
+                "owner={owner}",
+                "-F",
+                "name={repo}",
+                "-F",
+                &number_arg,
+            ],
+            token,
+        )?;
+        parse_pending_required_checks(&json_str)
+    }
+
+    /// Remove a PR from GitHub's merge queue.
+    pub fn dequeue_pr(pr: &str, token: &str) -> Result<(), String> {
+        let json_str = Self::run_pr_mutation(DEQUEUE_PR_MUTATION, pr, token)?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        match v.get("errors") {
+            Some(errors) => Err(graphql_error_messages(errors)),
+            None => Ok(()),
+        }
+    }
+
+    fn run_pr_mutation(mutation: &str, pr: &str, token: &str) -> Result<String, String> {
+        let id = Self::get_pr_node_id(pr, token)?;
+        let query_arg = format!("query={}", mutation);
+        let id_arg = format!("id={}", id);
+        // gh exits non-zero on GraphQL errors and prints them on stderr
+        try_gh(&["api", "graphql", "-f", &query_arg, "-f", &id_arg], token)
+            .map_err(|e| e.trim_start_matches("GraphQL: ").to_string())
+    }
+
+    /// The stack `pr` sits on, bottom first and ending with `pr`: follow base branches down
+    /// through open PRs until reaching one that targets `protected_branches` (or a base no open
+    /// PR heads).
+    pub fn find_stack(
+        pr: &str,
+        protected_branches: &[String],
+        token: &str,
+    ) -> Result<Vec<String>, String> {
+        let mut current = pr.to_string();
+        let mut stack = Vec::new();
+        for _ in 0..MAX_STACK_DEPTH {
+            stack.insert(0, current.clone());
+            let json_str = try_gh(&["pr", "view", &current, "--json", "baseRefName"], token)?;
+            let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+            let base = v["baseRefName"]
+                .as_str()
+                .ok_or_else(|| format!("PR {} JSON does not contain 'baseRefName'", current))?;
+            if protected_branches.iter().any(|b| b == base) {
+                return Ok(stack);
+            }
+            let json_str = try_gh(
+                &[
+                    "pr", "list", "--head", base, "--state", "open", "--json", "number",
+                ],
+                token,
+            )?;
+            let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+            match v[0]["number"].as_u64() {
+                Some(parent) => current = parent.to_string(),
+                None => return Ok(stack),
+            }
+        }
+        Err(format!(
+            "stack under PR {} is deeper than {} PRs",
+            pr, MAX_STACK_DEPTH
+        ))
+    }
+
+    /// Numbers of the open PRs stacked directly on `branch` (based on it).
+    pub fn stacked_on(branch: &str, token: &str) -> Result<Vec<String>, String> {
+        let json_str = try_gh(
+            &[
+                "pr", "list", "--base", branch, "--state", "open", "--json", "number",
+            ],
+            token,
+        )?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        Ok(v.as_array()
+            .map(|prs| {
+                prs.iter()
+                    .filter_map(|pr| pr["number"].as_u64())
+                    .map(|n| n.to_string())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Like `get_pr_base_branch`, but an error instead of falling back to "main".
+    pub fn try_get_pr_base_branch(pr: &str, gh_token: &str) -> Result<String, String> {
+        let json_str = try_gh(&["pr", "view", pr, "--json", "baseRefName"], gh_token)?;
+        let v: Value = serde_json::from_str(&json_str).map_err(|e| e.to_string())?;
+        v["baseRefName"]
+            .as_str()
+            .map(|base| base.to_string())
+            .ok_or_else(|| format!("PR {} JSON does not contain 'baseRefName'", pr))
     }
 
     pub fn get_pr_base_branch(pr: &str, gh_token: &str) -> String {
@@ -81,6 +557,12 @@ impl GitHubAction {
         serde_json::from_str(json).unwrap()
     }
 
+    /// Like `from_json`, but returns an error for contexts without a pull request
+    /// (e.g. merge queue `push` or `merge_group` events).
+    pub fn try_from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
     pub fn repo_owner(&self) -> &str {
         let repo_parts: Vec<&str> = self.repository.split('/').collect();
         repo_parts.first().expect("Invalid REPOSITORY format")
@@ -94,3 +576,48 @@ impl GitHubAction {
         self.base_ref.as_deref().unwrap_or("main")
     }
 }
+
+/// The parts of a GitHub Actions `github` context that exist for every event type.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct GitHubContext {
+    #[serde(default)]
+    pub event_name: Option<String>,
+    #[serde(default, rename = "ref")]
+    pub git_ref: Option<String>,
+    #[serde(default)]
+    pub head_ref: Option<String>,
+    #[serde(default)]
+    pub base_ref: Option<String>,
+    #[serde(default)]
+    pub event: ContextEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ContextEvent {
+    #[serde(default)]
+    pub merge_group: Option<MergeGroup>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MergeGroup {
+    pub base_ref: String,
+}
+
+impl GitHubContext {
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    /// Branch the tested change targets: `base_ref` for pull request events, or the merge
+    /// group's base for `merge_group` events. None for events without a base (e.g. `push`); for
+    /// pushes to a merge queue branch see `test_sim::merge_branch_base`.
+    pub fn base_branch(&self) -> Option<&str> {
+        if let Some(base) = self.base_ref.as_deref().filter(|b| !b.is_empty()) {
+            return Some(base);
+        }
+        self.event
+            .merge_group
+            .as_ref()
+            .map(|mg| mg.base_ref.trim_start_matches("refs/heads/"))
+    }
+}
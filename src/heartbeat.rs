@@ -0,0 +1,71 @@
+//! Heartbeat file for long-running `generate --daemon` processes.
+//!
+//! The file is rewritten atomically (write to a temp file, then rename) so a supervisor polling
+//! it never reads a partial write. A stale `updated_at` means the process is stuck or gone.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct HeartbeatState {
+    pub pid: u32,
+    /// "starting", "running", "backing-off" or "stopped"
+    pub status: String,
+    pub started_at: String,
+    pub updated_at: String,
+    /// Generate windows completed without an error
+    pub windows: u64,
+    pub prs_created: u64,
+    pub last_pr: Option<u32>,
+    pub last_success_at: Option<String>,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl HeartbeatState {
+    pub fn new() -> Self {
+        let now = chrono::Utc::now().to_rfc3339();
+        HeartbeatState {
+            pid: std::process::id(),
+            status: "starting".to_string(),
+            started_at: now.clone(),
+            updated_at: now,
+            ..Default::default()
+        }
+    }
+}
+
+/// Write `state` to `path`, stamping `updated_at` with the current time.
+pub fn write(path: &Path, state: &HeartbeatState) -> std::io::Result<()> {
+    let mut state = state.clone();
+    state.updated_at = chrono::Utc::now().to_rfc3339();
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_string_pretty(&state)?)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_replaces_heartbeat() {
+        let path =
+            std::env::temp_dir().join(format!("mq_heartbeat_test_{}.json", std::process::id()));
+        let mut state = HeartbeatState::new();
+        write(&path, &state).unwrap();
+
+        state.status = "running".to_string();
+        state.prs_created = 3;
+        write(&path, &state).unwrap();
+
+        let read: HeartbeatState =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(read.status, "running");
+        assert_eq!(read.prs_created, 3);
+        assert_eq!(read.pid, std::process::id());
+        assert!(!path.with_extension("tmp").exists());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,91 @@
+//! Append-only JSON Lines record of what a load test did and when, for later reporting.
+//!
+//! Every line is one event: `{"timestamp": "<rfc3339>", "event": "<name>", ...fields}`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_LEDGER: &str = "mq-ledger.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub timestamp: String,
+    pub event: String,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+pub struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Ledger { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `event` with `fields` (a JSON object; other values are ignored).
+    pub fn record(&self, event: &str, fields: Value) -> std::io::Result<()> {
+        let entry = LedgerEntry {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            event: event.to_string(),
+            fields: match fields {
+                Value::Object(map) => map,
+                _ => Map::new(),
+            },
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+    }
+
+    /// Every entry recorded so far, oldest first.
+    pub fn read(&self) -> std::io::Result<Vec<LedgerEntry>> {
+        let content = std::fs::read_to_string(&self.path)?;
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(std::io::Error::from))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_record_appends_entries() {
+        let path =
+            std::env::temp_dir().join(format!("mq_ledger_test_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let ledger = Ledger::new(&path);
+
+        ledger
+            .record("phase_start", json!({"phase": "warmup", "index": 0}))
+            .unwrap();
+        ledger
+            .record("phase_end", json!({"phase": "warmup"}))
+            .unwrap();
+
+        let entries = ledger.read().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].event, "phase_start");
+        assert_eq!(entries[0].fields["phase"], "warmup");
+        assert_eq!(entries[0].fields["index"], 0);
+        assert_eq!(entries[1].event, "phase_end");
+        assert!(chrono::DateTime::parse_from_rfc3339(&entries[1].timestamp).is_ok());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,7 +1,19 @@
 pub mod cli;
 pub mod config;
 pub mod config_error;
+pub mod conflict;
+pub mod distribution;
 pub mod edit;
+pub mod flake;
 pub mod github;
+pub mod heartbeat;
+pub mod ledger;
 pub mod process;
+pub mod queue_sim;
+pub mod scenario;
+pub mod scheduler;
+pub mod shutdown;
+pub mod sweep;
+pub mod test_sim;
+pub mod trigger;
 pub mod trunk;
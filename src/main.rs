@@ -1,19 +1,40 @@
 use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 use std::{env, thread};
 
 use chrono::{DateTime, Utc};
 use clap::Parser;
 use confique::Config;
-use gen::cli::{Cli, Subcommands};
-use gen::config::{Conf, EnqueueTrigger};
+use gen::cli::{
+    Cli, Generate, QueueBranch, QueueCommand, QueueStatus, RunScenario, Simulate, Subcommands,
+    Sweep, TestSim,
+};
+use gen::config::{Conf, Mode, StackEnqueue};
 use gen::config_error::handle_config_load_error;
+use gen::conflict::{conflicting_pairs, pair_marker, PairPlanner};
 use gen::edit::edit_files_for_pr;
-use gen::github::GitHub;
-use gen::process::{git, run_cmd, try_gh, try_git, try_git_quiet};
-use gen::trunk::{submit_pull_request, upload_targets};
-use rand::Rng;
+use gen::github::{GitHub, GitHubAction, GitHubContext, PrQuery, PrSummary};
+use gen::heartbeat::{self, HeartbeatState};
+use gen::ledger::{Ledger, DEFAULT_LEDGER};
+use gen::process::{git, try_gh, try_git, try_git_quiet};
+use gen::queue_sim::{simulate_queue, QueueParams};
+use gen::scenario::Scenario;
+use gen::scheduler::{panic_message, Next, Scheduler};
+use gen::shutdown;
+use gen::sweep::{grid, parse_counts, parse_values, run_sweep, to_csv};
+use gen::test_sim::{detect_merge_run, merge_branch_base, simulate, SimContext};
+use gen::trigger::{dequeue, enqueue, get_repo_info, EnqueueVars};
+use gen::trunk::{
+    get_queue, get_submitted_pull_request, get_targets, queue_rows, set_queue_state, submitted_row,
+    upload_targets, QueueState,
+};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use regex::Regex;
 use serde_json::{to_string_pretty, Value};
 use walkdir::WalkDir;
@@ -28,9 +49,8 @@ fn get_first_github_token(cli: &Cli) -> String {
     github_tokens[0].clone()
 }
 
-fn get_txt_files(config: &Conf) -> std::io::Result<Vec<PathBuf>> {
-    let mut path = std::env::current_dir()?;
-    path.push(&config.pullrequest.change_code_path);
+fn get_txt_files(root: &Path, config: &Conf) -> std::io::Result<Vec<PathBuf>> {
+    let path = root.join(&config.pullrequest.change_code_path);
     let mut paths = Vec::new();
     for entry in WalkDir::new(&path) {
         let entry = entry?;
@@ -43,85 +63,166 @@ fn get_txt_files(config: &Conf) -> std::io::Result<Vec<PathBuf>> {
     Ok(paths)
 }
 
-fn housekeeping(config: &Conf, gh_token: &str) {
-    for _ in 0..3 {
-        let json_str = try_gh(
-            &[
-                "pr",
-                "list",
-                "--limit=1000",
-                "--json",
-                "number,mergeable,comments",
-            ],
-            gh_token,
-        )
-        .expect("Failed to list PRs");
-        let v: Value = serde_json::from_str(&json_str).expect("Failed to parse JSON");
-
-        let mut has_unknown = false;
-        let mut requeued: HashSet<String> = HashSet::new();
-        if let Some(array) = v.as_array() {
-            for item in array {
-                let mergeable = item["mergeable"].as_str().unwrap_or("");
-                let pr = item["number"].as_i64().unwrap_or(0).to_string();
-                match mergeable {
-                    "UNKNOWN" => {
-                        has_unknown = true;
-                    }
-                    "CONFLICTING" => {
-                        GitHub::close(&pr, gh_token);
-                        println!("closed pr: {} (had merge conflicts)", &pr);
-                    }
-                    "MERGEABLE" => {
-                        if requeued.contains(&pr) {
-                            continue;
-                        }
-                        let comments = item["comments"].as_array().unwrap();
-                        'comment: for comment in comments.iter() {
-                            let body = comment["body"].as_str().unwrap_or("");
-                            let created_at_str = comment["createdAt"].as_str().unwrap_or("");
-
-                            if created_at_str.is_empty() {
-                                continue 'comment;
-                            }
-
-                            let stale_age = Utc::now() - config.close_stale_after_duration();
-                            let created_at = DateTime::parse_from_rfc3339(created_at_str)
-                                .expect("Unable to parse datetime")
-                                .with_timezone(&Utc);
-
-                            if created_at > stale_age {
-                                continue 'comment; // The datetime was less than stale age
-                            }
-
-                            if config
-                                .pullrequest
-                                .detect_stale_pr_comments
-                                .iter()
-                                .any(|s| body.contains(s))
-                            {
-                                //enqueue(&pr, config, cli, gh_token);
-                                GitHub::close(&pr, gh_token);
-                                println!("closed stale pr: {}", &pr);
-                                requeued.insert(pr.to_owned());
-                            }
-                        }
-                    }
-                    _ => {
-                        // handle other states
-                    }
+/// Close `pr`, logging instead of failing so one bad PR does not end a housekeeping pass.
+/// Returns `true` if the PR was closed.
+fn close_pr(pr: &str, reason: &str, gh_token: &str) -> bool {
+    match GitHub::try_close(pr, gh_token) {
+        Ok(_) => {
+            println!("closed {}", reason);
+            true
+        }
+        Err(e) => {
+            eprintln!("could not close pr {}: {}", pr, e);
+            false
+        }
+    }
+}
+
+/// Close a PR with conflicts, or one whose enqueue comments have gone stale.
+/// PRs whose mergeability is still `UNKNOWN` are left alone.
+/// Returns `true` if the PR was closed.
+fn handle_mergeable_state(config: &Conf, item: &PrSummary, gh_token: &str) -> bool {
+    let pr = item.number.to_string();
+    match item.mergeable.as_str() {
+        "CONFLICTING" => close_pr(&pr, &format!("pr: {} (had merge conflicts)", pr), gh_token),
+        "MERGEABLE" => {
+            'comment: for comment in item.comments.iter() {
+                if comment.created_at.is_empty() {
+                    continue 'comment;
+                }
+
+                let stale_age = Utc::now() - config.close_stale_after_duration();
+                let Ok(created_at) = DateTime::parse_from_rfc3339(&comment.created_at) else {
+                    eprintln!(
+                        "pr {}: unable to parse comment time '{}'",
+                        pr, comment.created_at
+                    );
+                    continue 'comment;
+                };
+                let created_at = created_at.with_timezone(&Utc);
+
+                if created_at > stale_age {
+                    continue 'comment; // The datetime was less than stale age
+                }
+
+                if config
+                    .pullrequest
+                    .detect_stale_pr_comments
+                    .iter()
+                    .any(|s| comment.body.contains(s))
+                {
+                    //enqueue(&pr, config, cli, gh_token);
+                    return close_pr(&pr, &format!("stale pr: {}", pr), gh_token);
                 }
             }
+            false
+        }
+        _ => {
+            // handle other states
+            false
+        }
+    }
+}
 
-            if has_unknown {
-                thread::sleep(Duration::from_secs(10));
-            } else {
-                return;
+/// One housekeeping pass over all open PRs.
+///
+/// `handled` holds PRs already closed by an earlier pass (in watch mode); they are skipped in
+/// case GitHub still lists them as open, and newly closed PRs are added to it.
+/// Fails only when the open PRs cannot be listed; PRs that cannot be closed are logged.
+fn housekeeping(config: &Conf, gh_token: &str, handled: &mut HashSet<u32>) -> Result<(), String> {
+    let open_prs = PrQuery {
+        states: vec!["OPEN".to_string()],
+        ..Default::default()
+    };
+    let prs =
+        GitHub::list_prs(&open_prs, gh_token).map_err(|e| format!("Failed to list PRs: {}", e))?;
+
+    // GitHub computes mergeability lazily, so freshly listed PRs often report UNKNOWN.
+    // Handle everything that is already resolved and poll only the rest.
+    let mut unknown: Vec<PrSummary> = Vec::new();
+    for item in prs {
+        if handled.contains(&item.number) {
+            continue;
+        }
+        if item.mergeable == "UNKNOWN" {
+            unknown.push(item);
+        } else if handle_mergeable_state(config, &item, gh_token) {
+            handled.insert(item.number);
+        }
+    }
+
+    let deadline = Instant::now() + config.mergeable_poll_timeout_duration();
+    let mut attempt = 0;
+    while !unknown.is_empty() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        let delay = config.mergeable_backoff(attempt).min(deadline - now);
+        println!(
+            "waiting {}s for mergeability of {} pr(s)",
+            delay.as_secs_f32(),
+            unknown.len()
+        );
+        if !shutdown::sleep(delay) {
+            return Ok(());
+        }
+        attempt += 1;
+
+        unknown.retain_mut(|item| {
+            match GitHub::get_pr_mergeable(&item.number.to_string(), gh_token) {
+                Ok(state) if state != "UNKNOWN" => {
+                    item.mergeable = state;
+                    if handle_mergeable_state(config, item, gh_token) {
+                        handled.insert(item.number);
+                    }
+                    false
+                }
+                Ok(_) => true,
+                Err(e) => {
+                    eprintln!("could not poll mergeability of pr {}: {}", item.number, e);
+                    true
+                }
             }
-        } else {
-            return;
+        });
+    }
+
+    if !unknown.is_empty() {
+        let numbers: Vec<String> = unknown.iter().map(|pr| pr.number.to_string()).collect();
+        println!(
+            "mergeability still unknown after {} for {} pr(s): {}",
+            config.pullrequest.mergeable_poll_timeout,
+            numbers.len(),
+            numbers.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Run housekeeping every `interval` until a shutdown signal arrives.
+/// The token is resolved once and PRs closed in one pass are remembered for the next.
+/// A failed pass is logged and the next one runs as scheduled.
+fn watch_housekeeping(config: &Conf, gh_token: &str, interval: Duration) {
+    shutdown::install_handler();
+    let mut handled: HashSet<u32> = HashSet::new();
+    let mut pass = 0u64;
+    while !shutdown::requested() {
+        pass += 1;
+        println!("housekeeping pass {}", pass);
+        if let Err(e) = housekeeping(config, gh_token, &mut handled) {
+            eprintln!("housekeeping pass {} failed: {}", pass, e);
+        }
+        println!(
+            "housekeeping pass {} done ({} pr(s) closed so far) // next in {}s",
+            pass,
+            handled.len(),
+            interval.as_secs()
+        );
+        if !shutdown::sleep(interval) {
+            break;
         }
     }
+    println!("housekeeping stopped after {} pass(es)", pass);
 }
 
 fn configure_git(config: &Conf) {
@@ -129,127 +230,532 @@ fn configure_git(config: &Conf) {
     git(&["config", "user.name", &config.git.name]);
 }
 
-fn get_repo_info() -> Result<(String, String), String> {
-    let remote_url = git(&["config", "--get", "remote.origin.url"]);
-    let re = Regex::new(r"[:/]([^/]+)/([^/]+?)(?:\.git)?$").unwrap();
+/// Impacted targets for a test-sim run, from the first available source: the PR body in the
+/// `--github-json` event file, the body of `--pr`, or the files changed relative to `--diff-base`.
+fn impacted_targets(args: &TestSim, config: &Conf, cli: &Cli) -> Vec<String> {
+    if let Some(ref path) = args.github_json {
+        let github_json = std::fs::read_to_string(path).expect("Failed to read file");
+        // Merge queue events carry no pull request; fall through to the diff for those.
+        if let Ok(ga) = GitHubAction::try_from_json(&github_json) {
+            return get_targets(ga.event.pull_request.body.as_deref().unwrap_or(""));
+        }
+    }
+
+    if let Some(ref pr) = args.pr {
+        let token = cli.get_github_tokens().first().cloned().unwrap_or_default();
+        return match GitHub::get_pr_body(pr, &token) {
+            Ok(body) => get_targets(&body),
+            Err(e) => {
+                eprintln!("Warning: could not read body of PR {}: {}", pr, e);
+                Vec::new()
+            }
+        };
+    }
+
+    let diff_base = args.diff_base.clone().unwrap_or_else(|| {
+        let base = config
+            .pullrequest
+            .protected_branches
+            .first()
+            .map_or("main", |b| b.as_str());
+        format!("origin/{}", base)
+    });
+    match try_git(&["diff", "--name-only", &format!("{}...HEAD", diff_base)]) {
+        Ok(files) => files.lines().map(|l| l.to_string()).collect(),
+        Err(e) => {
+            eprintln!("Warning: could not diff against {}: {}", diff_base, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Run a simulated test and return the process exit code (see `TestRunReport::exit_code`).
+fn simulate_test(mut config: Conf, args: &TestSim, cli: &Cli) -> i32 {
+    let context = args
+        .github_json
+        .as_ref()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|json| GitHubContext::from_json(&json).ok());
+    let (is_merge, reason) = detect_merge_run(
+        &config.test.merge_branch_patterns,
+        |key| env::var(key).ok(),
+        context.as_ref(),
+    );
+
+    if !is_merge {
+        println!("no flake or sleep when running on pr branch ({})", reason);
+        return 0;
+    }
+    println!("merge queue run detected ({})", reason);
+
+    if config.test.branches.is_some() {
+        match test_base_branch(
+            args,
+            context.as_ref(),
+            &config.test.merge_branch_patterns,
+            cli,
+        ) {
+            Some(base) if config.apply_branch_profile(&base) => {
+                println!("Using [test.branches.{}] profile", base)
+            }
+            Some(base) => println!("No [test.branches] profile for {}", base),
+            None => println!("Base branch unknown - using the [test] defaults"),
+        }
+    }
+
+    let impacted = impacted_targets(args, &config, cli);
+    println!("Impacted targets: {:?}", impacted);
+    for (key, target) in config.matching_targets(&impacted) {
+        println!("Target {} flake rate: {}", key, target.flake_rate);
+    }
+    println!("Flake rate: {}", config.failure_probability(&impacted));
+
+    let ctx = SimContext {
+        seed: args.seed.unwrap_or_else(|| rand::thread_rng().gen()),
+        sha: tested_sha(args),
+        attempt: run_attempt(),
+        outage: config.outage_active(SystemTime::now()),
+        logical_conflict: !config.pullrequest.logical_conflict_file.is_empty()
+            && Path::new(&config.pullrequest.logical_conflict_file).exists(),
+        pairwise_conflicts: conflicting_pairs(&config.pullrequest.pairwise_conflict_dir),
+        impacted,
+    };
+    println!(
+        "Seed: {} (commit {}, attempt {})",
+        ctx.seed, ctx.sha, ctx.attempt
+    );
+    if ctx.outage {
+        println!(
+            "Simulated outage in progress - adding {} flake rate",
+            config.test.outage_flake_rate
+        );
+    }
+
+    let report = simulate(&config, &ctx, &mut StdRng::seed_from_u64(ctx.seed));
 
-    if let Some(caps) = re.captures(&remote_url) {
-        let owner = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        let name = caps.get(2).map_or("", |m| m.as_str()).to_string();
-        Ok((owner, name))
+    if report.timed_out {
+        println!(
+            "simulating a run that does not finish - sleeping until the {}s timeout",
+            config.test_timeout().as_secs()
+        );
     } else {
-        Err("Could not parse repository owner and name from remote URL".to_string())
+        println!("sleeping for {:.1} seconds", report.duration_secs);
+    }
+    thread::sleep(report.duration());
+
+    for target in &report.targets {
+        println!(
+            "{}: {:?} in {:.1}s{}",
+            target.name,
+            target.status,
+            target.duration_secs,
+            target
+                .reason
+                .as_ref()
+                .map(|r| format!(" ({})", r))
+                .unwrap_or_default()
+        );
     }
+
+    if let Some(dir) = args
+        .results_dir
+        .as_ref()
+        .or(config.test.results_dir.as_ref())
+    {
+        match report.write(dir) {
+            Ok(_) => println!("wrote test results to {}", dir),
+            Err(e) => eprintln!("Failed to write test results to {}: {}", dir, e),
+        }
+    }
+
+    if report.timed_out {
+        println!("test run timed out (exit code {})", report.exit_code());
+    }
+    report.exit_code()
 }
 
-/// `as_stack`: tip of a multi-PR stack — with `merge.trigger = comment`, posts `/trunk stack`
-/// instead of `merge.comment` (typically `/trunk merge`).
-fn enqueue(pr: &str, config: &Conf, cli: &Cli, gh_token: &str, as_stack: bool) {
-    const TRUNK_STACK_COMMENT: &str = "/trunk stack";
-    match config.merge.trigger {
-        EnqueueTrigger::Comment => {
-            let body = if as_stack {
-                TRUNK_STACK_COMMENT
-            } else {
-                config.merge.comment.as_str()
-            };
-            if body.is_empty() {
-                eprintln!("Cannot enqueue PR because merge 'trigger' is set to comment but no comment was provided");
-                return;
-            }
-            GitHub::comment(pr, body, gh_token);
-        }
-        EnqueueTrigger::Label => {
-            if config.merge.labels.is_empty() {
-                eprintln!("Cannot enqueue PR because merge 'trigger' is set to label but no labels were provided");
-                return;
-            }
-            let labels: Vec<&str> = config.merge.labels.split(',').map(|s| s.trim()).collect();
-            for lbl in &labels {
-                GitHub::add_label(pr, lbl, gh_token);
-            }
-        }
-        EnqueueTrigger::Run => {
-            if config.merge.run.is_empty() {
-                eprintln!("Cannot enqueue PR because merge 'trigger' is set to run but no run command was provided");
-                return;
-            }
-            // perform token replacement for pr
-            let cmd = config.merge.run.replace("{{PR_NUMBER}}", pr);
-            println!("run commd {}", cmd);
-            let result = run_cmd(&cmd);
-            println!("merge run results: {}", result);
-        }
-
-        EnqueueTrigger::Api => {
-            // TRUNK_TOKEN will be checked at runtime in submit_pull_request
-            match get_repo_info() {
-                Ok((owner, name)) => {
-                    let pr_number: u32 = match pr.parse() {
-                        Ok(num) => num,
-                        Err(_) => {
-                            eprintln!("Invalid PR number: {}", pr);
-                            return;
-                        }
-                    };
-                    // Get the PR's base branch
-                    let target_branch = GitHub::get_pr_base_branch(pr, gh_token);
-                    println!("Enqueuing PR {} targeting branch: {}", pr, target_branch);
-                    match submit_pull_request(
-                        &owner,
-                        &name,
-                        pr_number,
-                        &target_branch,
-                        None, // Default priority, could be made configurable
-                        &config.trunk.api,
-                        cli,
-                    ) {
-                        Ok(_) => println!(
-                            "Successfully submitted PR {} to Trunk merge queue (target: {})",
-                            pr, target_branch
-                        ),
-                        Err(e) => {
-                            eprintln!("Failed to submit PR {} to Trunk merge queue: {}", pr, e);
-                            std::process::exit(1);
-                        }
-                    }
-                }
+/// Base branch of the change under test, used to pick a `[test.branches]` profile: from the
+/// `--github-json` event, then the merge queue branch name, then `--pr`, then GITHUB_BASE_REF.
+fn test_base_branch(
+    args: &TestSim,
+    context: Option<&GitHubContext>,
+    merge_branch_patterns: &[String],
+    cli: &Cli,
+) -> Option<String> {
+    if let Some(base) = context.and_then(|c| c.base_branch()) {
+        return Some(base.to_string());
+    }
+    if let Some(base) = merge_branch_base(merge_branch_patterns, |key| env::var(key).ok(), context)
+    {
+        return Some(base);
+    }
+    if let Some(ref pr) = args.pr {
+        let token = cli.get_github_tokens().first().cloned().unwrap_or_default();
+        return match GitHub::try_get_pr_base_branch(pr, &token) {
+            Ok(base) => Some(base),
+            Err(e) => {
+                eprintln!(
+                    "Warning: could not read the base branch of PR {}: {}",
+                    pr, e
+                );
+                None
+            }
+        };
+    }
+    env::var("GITHUB_BASE_REF").ok().filter(|b| !b.is_empty())
+}
+
+/// Commit under test: the PR head from `--github-json`, otherwise the checked out HEAD.
+fn tested_sha(args: &TestSim) -> String {
+    if let Some(ref path) = args.github_json {
+        let github_json = std::fs::read_to_string(path).expect("Failed to read file");
+        if let Ok(ga) = GitHubAction::try_from_json(&github_json) {
+            return ga.event.pull_request.head.sha;
+        }
+    }
+    try_git_quiet(&["rev-parse", "HEAD"]).unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// 1-based attempt number of this CI run (GitHub Actions re-runs bump GITHUB_RUN_ATTEMPT).
+fn run_attempt() -> u32 {
+    env::var("GITHUB_RUN_ATTEMPT")
+        .ok()
+        .and_then(|a| a.parse().ok())
+        .unwrap_or(1)
+}
+
+/// `--branch`, or the first protected branch.
+fn queue_branch(config: &Conf, branch: &Option<String>) -> String {
+    branch.clone().unwrap_or_else(|| {
+        config
+            .pullrequest
+            .protected_branches
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "main".to_string())
+    })
+}
+
+/// `mq queue pause|drain|resume`
+fn set_queue(
+    config: &Conf,
+    cli: &Cli,
+    args: &QueueBranch,
+    state: QueueState,
+) -> anyhow::Result<()> {
+    let (owner, name) = get_repo_info().map_err(|e| anyhow::anyhow!(e))?;
+    let branch = queue_branch(config, &args.branch);
+    set_queue_state(&owner, &name, &branch, state, &config.trunk.api, cli)
+        .map_err(|e| anyhow::anyhow!("Failed to set the {} queue to {:?}: {}", branch, state, e))?;
+    println!("{} queue is now {:?}", branch, state);
+    Ok(())
+}
+
+/// Trunk queues paused by `generate --pause-during-setup`. Each is put back in the state it was
+/// in (running or draining) when this is dropped, so an error or panic part-way through a burst
+/// never leaves a queue paused.
+struct PausedQueues<'a> {
+    config: &'a Conf,
+    cli: &'a Cli,
+    owner: String,
+    name: String,
+    /// Paused branches and the state to restore
+    branches: Vec<(String, QueueState)>,
+}
+
+impl<'a> PausedQueues<'a> {
+    /// Pause the queue of every protected branch. Queues that are already paused are left alone.
+    fn pause(config: &'a Conf, cli: &'a Cli) -> anyhow::Result<Self> {
+        let (owner, name) = get_repo_info().map_err(|e| anyhow::anyhow!(e))?;
+        let mut paused = PausedQueues {
+            config,
+            cli,
+            owner,
+            name,
+            branches: Vec::new(),
+        };
+        for branch in &config.pullrequest.protected_branches {
+            let queue = get_queue(&paused.owner, &paused.name, branch, &config.trunk.api, cli)
+                .map_err(|e| anyhow::anyhow!("Failed to read the {} queue: {}", branch, e))?;
+            let state: QueueState = serde_json::from_value(Value::String(queue.state.clone()))
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "the {} queue is in unknown state '{}' - not pausing it",
+                        branch,
+                        queue.state
+                    )
+                })?;
+            if state == QueueState::Paused {
+                println!("the {} queue is already paused", branch);
+                continue;
+            }
+            set_queue_state(
+                &paused.owner,
+                &paused.name,
+                branch,
+                QueueState::Paused,
+                &config.trunk.api,
+                cli,
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to pause the {} queue: {}", branch, e))?;
+            println!("paused the {} queue during setup", branch);
+            paused.branches.push((branch.clone(), state));
+        }
+        Ok(paused)
+    }
+}
+
+impl Drop for PausedQueues<'_> {
+    fn drop(&mut self) {
+        for (branch, state) in &self.branches {
+            match set_queue_state(
+                &self.owner,
+                &self.name,
+                branch,
+                *state,
+                &self.config.trunk.api,
+                self.cli,
+            ) {
+                Ok(()) => println!("restored the {} queue to {:?}", branch, state),
+                Err(e) => eprintln!(
+                    "could not restore the {} queue to {:?}: {}",
+                    branch, state, e
+                ),
+            }
+        }
+    }
+}
+
+/// Impacted targets listed in a PR's body (`deps=[...]`); None without a GitHub token.
+fn expected_targets(pr: u32, token: &str) -> Option<Vec<String>> {
+    if token.is_empty() {
+        return None;
+    }
+    GitHub::get_pr_body(&pr.to_string(), token)
+        .ok()
+        .map(|body| get_targets(&body))
+}
+
+/// `mq queue status`: the queue's PRs (or just `--pr`) as the Trunk API reports them, with the
+/// targets listed in each PR body next to the ones Trunk has received.
+fn queue_status(config: &Conf, cli: &Cli, args: &QueueStatus) -> anyhow::Result<()> {
+    let (owner, name) = get_repo_info().map_err(|e| anyhow::anyhow!(e))?;
+    let branch = queue_branch(config, &args.branch);
+    let queue = get_queue(&owner, &name, &branch, &config.trunk.api, cli)
+        .map_err(|e| anyhow::anyhow!("Failed to read the {} queue: {}", branch, e))?;
+
+    // getQueue has no readiness, targets or batch, so every PR is also looked up on its own
+    let token = cli.get_github_tokens().first().cloned().unwrap_or_default();
+    let mut rows = Vec::new();
+    if args.pr.is_empty() {
+        for queued in queue_rows(&queue) {
+            let number = queued.pr_number;
+            let mut row = match get_submitted_pull_request(
+                &owner,
+                &name,
+                number,
+                &branch,
+                &config.trunk.api,
+                cli,
+            ) {
+                Ok(pr) => submitted_row(&queue, &pr),
                 Err(e) => {
-                    eprintln!("Failed to get repository information: {}", e);
-                    std::process::exit(1);
+                    eprintln!("warning: could not read PR {}: {}", number, e);
+                    queued
                 }
-            }
+            };
+            row.expected_targets = expected_targets(number, &token);
+            rows.push(row);
+        }
+    } else {
+        for &number in &args.pr {
+            let pr =
+                get_submitted_pull_request(&owner, &name, number, &branch, &config.trunk.api, cli)
+                    .map_err(|e| anyhow::anyhow!("Failed to read PR {}: {}", number, e))?;
+            let mut row = submitted_row(&queue, &pr);
+            row.expected_targets = expected_targets(number, &token);
+            rows.push(row);
         }
     }
+
+    if args.json {
+        let output = serde_json::json!({ "queue": queue, "prs": rows });
+        println!("{}", to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} queue: {} ({} PRs enqueued)",
+        queue.branch,
+        queue.state,
+        queue.enqueued_pull_requests.len()
+    );
+    println!(
+        "{:>8}  {:<22} {:>8}  {:<8} {:<12} {:<16} expected targets",
+        "PR", "state", "position", "priority", "batch", "targets"
+    );
+    for row in rows {
+        // The targets Trunk has when it lists them, otherwise just whether it has any
+        let targets = match (row.targets, row.targets_uploaded) {
+            (Some(targets), _) => targets.join(","),
+            (None, Some(true)) => "uploaded".to_string(),
+            (None, Some(false)) => "missing".to_string(),
+            (None, None) => "-".to_string(),
+        };
+        println!(
+            "{:>8}  {:<22} {:>8}  {:<8} {:<12} {:<16} {}",
+            row.pr_number,
+            row.state,
+            row.position.map_or("-".to_string(), |p| p.to_string()),
+            row.priority.as_deref().unwrap_or("-"),
+            row.testing_batch.as_deref().unwrap_or("-"),
+            targets,
+            row.expected_targets
+                .map_or("-".to_string(), |targets| targets.join(","))
+        );
+    }
+    Ok(())
 }
 
-fn simulate_test(config: &Conf) -> bool {
-    let is_merge_str = env::var("IS_MERGE").unwrap_or_else(|_| String::from("false"));
-    let is_merge = is_merge_str.to_lowercase() == "true";
+/// `mq simulate`: run the offline queue model against the loaded config and print the result.
+fn run_simulation(config: &Conf, args: &Simulate) -> anyhow::Result<()> {
+    let duration = parse_duration::parse(&args.duration)
+        .map_err(|e| anyhow::anyhow!("invalid --duration '{}': {:?}", args.duration, e))?;
+    let parallel = match args.mode.as_deref() {
+        Some(mode) => mode == "parallel",
+        None => matches!(config.mode, Mode::ParallelQueue),
+    };
+    let params = QueueParams {
+        parallel,
+        batch_size: args.batch_size,
+        concurrency: args.concurrency,
+        optimistic: args.optimistic,
+        retries: args.retries,
+        duration,
+        targets: args.targets,
+        seed: args.seed.unwrap_or_else(|| rand::thread_rng().gen()),
+    };
+    let report = simulate_queue(config, &params).map_err(|e| anyhow::anyhow!(e))?;
 
-    if !is_merge {
-        println!("no flake or sleep when running on pr branch");
-        return true;
+    if args.json {
+        let output = serde_json::json!({ "params": params, "report": report });
+        println!("{}", to_string_pretty(&output)?);
+        return Ok(());
     }
 
-    println!("sleeping for {} seconds", config.sleep_duration().as_secs());
-    thread::sleep(config.sleep_duration());
+    println!(
+        "{} queue, batch size {}, concurrency {}, optimistic {}, retries {}, seed {}",
+        if params.parallel {
+            "parallel"
+        } else {
+            "single"
+        },
+        params.batch_size,
+        params.concurrency,
+        params.optimistic,
+        params.retries,
+        params.seed
+    );
+    println!(
+        "PRs: {} arrived, {} merged, {} failed, {} still queued after {:.1}h",
+        report.arrived, report.merged, report.failed, report.queued, report.simulated_hours
+    );
+    println!("Throughput: {:.1} PRs/hour", report.throughput_per_hour);
+    println!(
+        "Latency (minutes): mean {:.1}, p50 {:.1}, p90 {:.1}, p99 {:.1}",
+        report.latency_mean_mins,
+        report.latency_p50_mins,
+        report.latency_p90_mins,
+        report.latency_p99_mins
+    );
+    println!(
+        "Test runs: {} ({} restarted, {} flaky failures, {} retries, {} bisections, {} optimistic merges), {:.1} CI hours",
+        report.test_runs,
+        report.restarted_runs,
+        report.flaky_failures,
+        report.retries,
+        report.bisections,
+        report.optimistic_merges,
+        report.ci_hours
+    );
+    println!("Max queue depth: {}", report.max_queue_depth);
+    Ok(())
+}
 
-    if !config.pullrequest.logical_conflict_file.is_empty()
-        && Path::new(&config.pullrequest.logical_conflict_file).exists()
-    {
-        // this pull request is simulating a "logical merge conflict" and should always fail
-        println!("Simulating logical merge conflict - failing test");
-        return false;
+/// `mq sweep`: simulate every combination of the swept parameters and print the results table.
+fn run_parameter_sweep(config: &Conf, args: &Sweep) -> anyhow::Result<()> {
+    let invalid = |flag: &str, e: String| anyhow::anyhow!("invalid --{}: {}", flag, e);
+    let flake_rates: Vec<f32> = match args.flake_rate {
+        Some(ref spec) => parse_values(spec)
+            .map_err(|e| invalid("flake-rate", e))?
+            .into_iter()
+            .map(|v| v as f32)
+            .collect(),
+        None => vec![config.test.flake_rate],
+    };
+    if flake_rates.iter().any(|r| !(0.0..=1.0).contains(r)) {
+        return Err(anyhow::anyhow!(
+            "--flake-rate values must be between 0.0 and 1.0"
+        ));
     }
+    let batch_sizes = parse_counts(&args.batch_size).map_err(|e| invalid("batch-size", e))?;
+    let concurrencies = parse_counts(&args.concurrency).map_err(|e| invalid("concurrency", e))?;
+    let requests_per_hour: Vec<Option<u32>> = match args.requests_per_hour {
+        Some(ref spec) => parse_counts(spec)
+            .map_err(|e| invalid("requests-per-hour", e))?
+            .into_iter()
+            .map(|r| Some(r as u32))
+            .collect(),
+        None => vec![None],
+    };
 
-    let mut rng = rand::thread_rng();
-    let random_float = rng.gen_range(0.0..1.0);
+    let duration = parse_duration::parse(&args.duration)
+        .map_err(|e| anyhow::anyhow!("invalid --duration '{}': {:?}", args.duration, e))?;
+    let base = QueueParams {
+        parallel: match args.mode.as_deref() {
+            Some(mode) => mode == "parallel",
+            None => matches!(config.mode, Mode::ParallelQueue),
+        },
+        batch_size: 1,
+        concurrency: 1,
+        optimistic: args.optimistic,
+        retries: args.retries,
+        duration,
+        targets: args.targets,
+        seed: args.seed.unwrap_or_else(|| rand::thread_rng().gen()),
+    };
 
-    println!("Random float: {}", random_float);
-    println!("Flake rate: {}", config.test.flake_rate);
+    let points = grid(
+        &flake_rates,
+        &batch_sizes,
+        &concurrencies,
+        &requests_per_hour,
+    );
+    let threads = args.threads.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    eprintln!(
+        "simulating {} combinations on {} threads (seed {})",
+        points.len(),
+        threads.min(points.len()),
+        base.seed
+    );
+    let rows = run_sweep(config, &base, &points, threads).map_err(|e| anyhow::anyhow!(e))?;
 
-    random_float > config.test.flake_rate
+    let table = match args.format.as_str() {
+        "json" => to_string_pretty(&rows)? + "\n",
+        _ => to_csv(&rows),
+    };
+    match args.output {
+        Some(ref path) => {
+            std::fs::write(path, table)?;
+            eprintln!("wrote {} rows to {}", rows.len(), path);
+        }
+        None => print!("{}", table),
+    }
+    Ok(())
 }
 
 fn maybe_add_logical_merge_conflict(last_pr: u32, config: &Conf) -> bool {
@@ -260,7 +766,7 @@ fn maybe_add_logical_merge_conflict(last_pr: u32, config: &Conf) -> bool {
     }
 
     // check if we should simulate a logical merge conflict with this pull request
-    if (last_pr + 1) % config.pullrequest.logical_conflict_every != 0 {
+    if !(last_pr + 1).is_multiple_of(config.pullrequest.logical_conflict_every) {
         return false;
     }
 
@@ -278,6 +784,26 @@ fn maybe_add_logical_merge_conflict(last_pr: u32, config: &Conf) -> bool {
     true
 }
 
+/// Write this PR's half of a pairwise logical conflict, if `pair` (from `PairPlanner`) gives it
+/// one. Returns the `(pair_id, side)` written.
+fn maybe_add_pairwise_conflict(pair: Option<(u32, char)>, config: &Conf) -> Option<(u32, char)> {
+    let (pair_id, side) = pair?;
+
+    println!(
+        "pairwise logical conflict {} side {} (every {} prs)",
+        pair_id, side, config.pullrequest.pairwise_conflict_every
+    );
+
+    std::fs::create_dir_all(&config.pullrequest.pairwise_conflict_dir)
+        .expect("Unable to create pairwise conflict directory");
+    let marker = pair_marker(&config.pullrequest.pairwise_conflict_dir, pair_id, side);
+    std::fs::write(&marker, format!("pairwise logical conflict {}\n", pair_id))
+        .expect("Unable to write pairwise conflict marker");
+
+    git(&["add", &marker.to_string_lossy()]);
+    Some((pair_id, side))
+}
+
 fn checkout_branch(branch: &str) -> Result<(), String> {
     // Check if branch exists locally (quietly - we expect this to fail if branch doesn't exist)
     let branch_exists_locally =
@@ -320,36 +846,16 @@ fn checkout_branch(branch: &str) -> Result<(), String> {
     Ok(())
 }
 
-/// Highest PR number seen in recent `gh pr list` output.
+/// Highest PR number in the repository, from a single query sorted by creation time.
 ///
 /// Used to seed `last_pr` so the first PR in a run picks a sensible `next_pr_number` for
-/// dependency distribution and logical-conflict cadence (`edit_files_for_pr`, etc.). We take the
-/// max over a page of PRs because `--limit=1` alone is an arbitrary row (often most recently
-/// *updated*), not the largest number. Head branch names are synthetic and do not embed this.
+/// dependency distribution and logical-conflict cadence (`edit_files_for_pr`, etc.). PR numbers
+/// are assigned in creation order, so the newest PR also carries the largest number.
 fn get_last_pr(gh_token: &str) -> u32 {
-    let result = try_gh(
-        &[
-            "pr", "list", "--state", "all", "-L", "5000", "--json", "number",
-        ],
-        gh_token,
-    );
-    if result.is_err() {
-        return 0;
-    }
-    let json_str = result.unwrap();
-
-    let v: Value = serde_json::from_str(&json_str).unwrap_or(Value::Null);
-
-    v.as_array()
-        .map(|pr_array| {
-            pr_array
-                .iter()
-                .filter_map(|pr| pr["number"].as_u64())
-                .map(|n| n as u32)
-                .max()
-                .unwrap_or(0)
-        })
-        .unwrap_or(0)
+    GitHub::get_last_pr_number(gh_token).unwrap_or_else(|e| {
+        eprintln!("Warning: could not look up last PR number: {}", e);
+        0
+    })
 }
 
 /// `stack-change/{stack_id}-{position}`. Stacking stores the returned name in `current_base`.
@@ -367,6 +873,7 @@ fn new_stack_id() -> String {
 }
 
 /// Build and open one generated PR (new branch, edits, commit, push, `gh pr create`).
+#[allow(clippy::too_many_arguments)]
 fn create_pull_request(
     filenames: &[String],
     last_pr: u32,
@@ -378,9 +885,10 @@ fn create_pull_request(
     stack_parent_pr: Option<u32>,
     stack_id: &str,
     position: usize,
-) -> Result<(String, usize, String), String> {
+    pair: Option<(u32, char)>,
+) -> Result<CreatedPr, String> {
     // When stacking above the base PR, pass that parent's GitHub PR number (None at stack bottom).
-    let current_branch = git(&["branch", "--show-current"]);
+    let current_branch = try_git(&["branch", "--show-current"])?;
 
     // Checkout the base branch (will fetch from origin if needed).
     // For stacked PRs, base_branch is the previous PR's head branch
@@ -396,10 +904,11 @@ fn create_pull_request(
     let deps_count = words.len();
 
     let branch_name = head_branch_for_stack(stack_id, position);
-    git(&["checkout", "-b", &branch_name]);
+    try_git(&["checkout", "-b", &branch_name])?;
 
     // Create logical conflict file if needed (after we're on the new branch)
     let lc = maybe_add_logical_merge_conflict(last_pr, config);
+    let pairwise = maybe_add_pairwise_conflict(pair, config);
 
     // Stage only the files that were modified (not all changes)
     for filename in filenames {
@@ -414,8 +923,8 @@ fn create_pull_request(
     if !dry_run {
         let result = try_git(&["push", "--set-upstream", "origin", "HEAD"]);
         if result.is_err() {
-            git(&["checkout", &current_branch]);
-            git(&["pull"]);
+            let _ = try_git(&["checkout", &current_branch]);
+            let _ = try_git(&["pull"]);
             return Err("could not push to origin".to_owned());
         }
     }
@@ -433,6 +942,9 @@ fn create_pull_request(
     if lc {
         title = format!("{} (logical-conflict)", title);
     }
+    if let Some((pair_id, side)) = pairwise {
+        title = format!("{} (pairwise-conflict {}{})", title, pair_id, side);
+    }
 
     let mut body = config.pullrequest.body.to_string();
     body.push_str("\n\n[test]\n");
@@ -441,10 +953,13 @@ fn create_pull_request(
         "logical conflict every: {}\n",
         config.pullrequest.logical_conflict_every
     ));
-    body.push_str(&format!(
-        "sleep for: {}s\n",
-        config.sleep_duration().as_secs()
-    ));
+    if config.pullrequest.pairwise_conflict_every > 0 {
+        body.push_str(&format!(
+            "pairwise conflict every: {}\n",
+            config.pullrequest.pairwise_conflict_every
+        ));
+    }
+    body.push_str(&format!("sleep for: {}\n", config.test.sleep_for));
     body.push_str(&format!(
         "close stale after: {}\n",
         config.pullrequest.close_stale_after
@@ -454,18 +969,14 @@ fn create_pull_request(
         config.pullrequest.requests_per_hour, base_branch
     ));
 
-    let mut first_letters: Vec<_> = words
-        .iter()
-        .map(|word| word.chars().next().unwrap().to_string())
-        .collect();
-
-    first_letters.sort();
-    first_letters.dedup();
+    let mut first_letters = first_letters(&words);
+    // Both sides of a pairwise conflict impact the marker directory, so a parallel queue tests
+    // them together instead of merging them side by side
+    if pairwise.is_some() {
+        first_letters.push(config.pullrequest.pairwise_conflict_dir.clone());
+    }
 
-    body.push_str(&format!(
-        "\n\ndeps=[{}]\n",
-        first_letters.into_iter().collect::<Vec<_>>().join(",")
-    ));
+    body.push_str(&format!("\n\ndeps=[{}]\n", first_letters.join(",")));
 
     if let Some((position, depth)) = stack_info {
         body.push_str("\n[stack]\n");
@@ -498,36 +1009,647 @@ fn create_pull_request(
     }
 
     if dry_run {
-        git(&["checkout", &current_branch]);
-        git(&["pull"]);
-        return Ok(((last_pr + 1).to_string(), deps_count, branch_name));
+        let _ = try_git(&["checkout", &current_branch]);
+        let _ = try_git(&["pull"]);
+        return Ok(CreatedPr {
+            pr: (last_pr + 1).to_string(),
+            deps_count,
+            head_branch: branch_name,
+            deps: first_letters,
+            pair: pairwise,
+        });
     }
 
     let result = try_gh(args.as_slice(), gh_token);
+    let pr_number = result.as_ref().ok().and_then(|pr_url| {
+        let re = Regex::new(r"(.*)/pull/(\d+)$").unwrap();
+        re.captures(pr_url.trim()).map(|caps| caps[2].to_string())
+    });
+    // Still on the PR's branch: an `a` marker written under a mispredicted number is renamed
+    let pairwise = match (pairwise, &pr_number) {
+        (Some((pair_id, 'a')), Some(pr)) => Some((
+            rekey_pair_marker(config, pr, pair_id, &title, gh_token),
+            'a',
+        )),
+        _ => pairwise,
+    };
 
     // no matter what is result - need to reset checkout and clean up
-    git(&["checkout", &current_branch]);
+    let _ = try_git(&["checkout", &current_branch]);
     // Clean up any uncommitted changes and untracked files
     let _ = try_git(&["reset", "--hard", "HEAD"]);
     let _ = try_git(&["clean", "-fd"]);
-    git(&["pull"]);
+    let _ = try_git(&["pull"]);
 
     if result.is_err() {
         return Err("could not create pull request".to_owned());
     }
 
-    let pr_url = result.unwrap();
-    let re = Regex::new(r"(.*)/pull/(\d+)$").unwrap();
-    let caps = re.captures(pr_url.trim()).unwrap();
-    let pr_number = caps.get(2).map_or("", |m| m.as_str());
+    Ok(CreatedPr {
+        pr: pr_number.unwrap_or_default(),
+        deps_count,
+        head_branch: branch_name,
+        deps: first_letters,
+        pair: pairwise,
+    })
+}
+
+/// A PR made by `create_pull_request`.
+struct CreatedPr {
+    pr: String,
+    deps_count: usize,
+    head_branch: String,
+    /// Targets listed in its body's `deps=[...]`
+    deps: Vec<String>,
+    /// Side of a pairwise conflict it carries, keyed by the pair id its marker is written under
+    pair: Option<(u32, char)>,
+}
+
+/// Move the `a` marker of pair `pair_id`, named after the number PR `pr` was predicted to get,
+/// to the number GitHub actually assigned, with a follow-up commit on the PR's checked out
+/// branch. Returns the pair id the marker ends up under.
+fn rekey_pair_marker(config: &Conf, pr: &str, pair_id: u32, title: &str, token: &str) -> u32 {
+    let Ok(number) = pr.parse::<u32>() else {
+        return pair_id;
+    };
+    if number == pair_id {
+        return pair_id;
+    }
+    let dir = &config.pullrequest.pairwise_conflict_dir;
+    let old = pair_marker(dir, pair_id, 'a')
+        .to_string_lossy()
+        .into_owned();
+    let new = pair_marker(dir, number, 'a').to_string_lossy().into_owned();
+    let message = format!("Pairwise conflict marker for pr {}", number);
+    let steps: [&[&str]; 3] = [
+        &["mv", &old, &new],
+        &["commit", "--no-verify", "-m", &message],
+        &["push"],
+    ];
+    for step in steps {
+        if let Err(e) = try_git(step) {
+            eprintln!(
+                "warning: pr {} keeps pairwise conflict marker {}a: git {} failed: {}",
+                pr, pair_id, step[0], e
+            );
+            return pair_id;
+        }
+    }
+    println!(
+        "pr {} got number {} - pairwise conflict marker renamed to {}a",
+        pr, number, number
+    );
+    let title = title.replace(
+        &format!("pairwise-conflict {}a", pair_id),
+        &format!("pairwise-conflict {}a", number),
+    );
+    if let Err(e) = GitHub::edit_title(pr, &title, token) {
+        eprintln!("warning: could not retitle pr {}: {}", pr, e);
+    }
+    number
+}
+
+fn generate(config: &Conf, cli: &Cli, args: &Generate) -> anyhow::Result<()> {
+    thread::scope(|scope| {
+        let scheduler = spawn_queue_scheduler(scope, cli);
+        let result = if args.daemon {
+            generate_daemon(config, cli, args, &scheduler)
+        } else {
+            generate_window(config, cli, &scheduler, None, args.pause_during_setup).map(|outcome| {
+                if outcome.scheduled > 0 && !shutdown::requested() {
+                    println!(
+                        "waiting for {} scheduled enqueues/dequeues/updates to finish",
+                        outcome.scheduled
+                    );
+                }
+            })
+        };
+        // Don't sit out the pending delays of a run that failed
+        if result.is_err() {
+            scheduler.stop();
+        }
+        result
+    })
+}
+
+/// How often a delayed enqueue re-checks a PR's required checks.
+const CHECKS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A generated PR on its way into the queue.
+struct NewPr {
+    pr: String,
+    token: String,
+    base_branch: String,
+    head_branch: String,
+    stack_id: String,
+    /// Impacted targets listed in the PR body
+    deps: Vec<String>,
+}
+
+impl NewPr {
+    fn enqueue_vars(&self) -> EnqueueVars {
+        EnqueueVars {
+            base_branch: Some(self.base_branch.clone()),
+            stack_id: Some(self.stack_id.clone()),
+            deps: Some(self.deps.clone()),
+        }
+    }
+}
+
+/// What happens to a PR once it is enqueued, picked per PR from `cancel_rate` and `update_rate`.
+struct AfterEnqueue {
+    /// Dequeue it this long later, as if the author changed their mind
+    cancel_after: Option<Duration>,
+    /// Push another change to it this long later; `true` amends and force-pushes
+    update_after: Option<(Duration, bool)>,
+}
+
+impl AfterEnqueue {
+    fn pick<R: Rng>(config: &Conf, rng: &mut R) -> Self {
+        let pullrequest = &config.pullrequest;
+        AfterEnqueue {
+            cancel_after: rng
+                .gen_bool(pullrequest.cancel_rate as f64)
+                .then(|| config.cancel_after_duration()),
+            update_after: rng.gen_bool(pullrequest.update_rate as f64).then(|| {
+                (
+                    config.update_after_duration(),
+                    rng.gen_bool(pullrequest.update_force_push_rate as f64),
+                )
+            }),
+        }
+    }
+
+    /// The follow-up jobs for `pr`, which was just enqueued; `enqueued` are the PRs that went
+    /// into the queue for it (more than `pr` itself for a stack on some queues).
+    fn jobs(&self, pr: &NewPr, enqueued: &[String]) -> Vec<(Instant, QueueJob)> {
+        let now = Instant::now();
+        let mut jobs = Vec::new();
+        if let Some((after, force)) = self.update_after {
+            println!(
+                "will {} pr {} in {}s",
+                if force {
+                    "force-push"
+                } else {
+                    "push a new commit to"
+                },
+                pr.pr,
+                after.as_secs()
+            );
+            jobs.push((
+                now + after,
+                QueueJob::Update {
+                    pr: pr.pr.clone(),
+                    branch: pr.head_branch.clone(),
+                    force,
+                    token: pr.token.clone(),
+                },
+            ));
+        }
+        if let Some(after) = self.cancel_after {
+            println!(
+                "will dequeue pr {} in {}s",
+                enqueued.join(", "),
+                after.as_secs()
+            );
+            jobs.push((
+                now + after,
+                QueueJob::Dequeue {
+                    prs: enqueued.to_vec(),
+                    token: pr.token.clone(),
+                },
+            ));
+        }
+        jobs
+    }
+}
+
+/// Queue work a generate window hands to the background scheduler.
+enum QueueJob {
+    Enqueue(Box<DelayedEnqueue>),
+    /// A simulated "author changed their mind" dequeue of the PRs one enqueue put in the queue
+    Dequeue {
+        prs: Vec<String>,
+        token: String,
+    },
+    /// A push to a queued PR's branch, as if the author kept working on it
+    Update {
+        pr: String,
+        branch: String,
+        force: bool,
+        token: String,
+    },
+}
+
+/// Enqueue of a PR after `enqueue_delay`. With `checks_deadline`, it waits for the PR's required
+/// checks to finish first, enqueuing anyway once the deadline passes. `after` follows a
+/// successful enqueue up with a dequeue or update. `rest` are stack members still to enqueue in
+/// order, the next one planned only once this one is in the queue.
+struct DelayedEnqueue {
+    pr: NewPr,
+    as_stack: bool,
+    priority: Option<String>,
+    checks_deadline: Option<Instant>,
+    after: AfterEnqueue,
+    rest: Vec<NewPr>,
+}
+
+impl DelayedEnqueue {
+    /// Plan the enqueue of `pr` `delay` from now, picking its priority and what follows it.
+    fn plan<R: Rng>(
+        config: &Conf,
+        cli: &Cli,
+        rng: &mut R,
+        pr: NewPr,
+        as_stack: bool,
+        delay: Duration,
+        rest: Vec<NewPr>,
+    ) -> (Instant, Self) {
+        // Dry-run PRs have no checks to wait for
+        let checks_deadline = (config.pullrequest.enqueue_wait_for_checks && !cli.dry_run)
+            .then(|| Instant::now() + delay + config.enqueue_checks_timeout_duration());
+        let job = DelayedEnqueue {
+            priority: config.pick_priority(rng),
+            after: AfterEnqueue::pick(config, rng),
+            pr,
+            as_stack,
+            checks_deadline,
+            rest,
+        };
+        (Instant::now() + delay, job)
+    }
+}
+
+/// Scheduler for queue jobs, each with the config of the window that planned it (scenario phases
+/// differ).
+type QueueScheduler<'a> = Scheduler<(&'a Conf, QueueJob)>;
+
+/// Start the queue job scheduler on `scope`, for the whole generate run rather than one window.
+fn spawn_queue_scheduler<'scope, 'env, 'a: 'scope>(
+    scope: &'scope thread::Scope<'scope, 'env>,
+    cli: &'scope Cli,
+) -> QueueScheduler<'a> {
+    Scheduler::spawn(scope, move |(config, job): (&'a Conf, QueueJob)| {
+        run_queue_job(job, config, cli).map(|job| (config, job))
+    })
+}
+
+/// Run one scheduled queue job. Failures are logged, not fatal.
+fn run_queue_job(job: QueueJob, config: &Conf, cli: &Cli) -> Next<QueueJob> {
+    match job {
+        QueueJob::Enqueue(job) => run_delayed_enqueue(*job, config, cli),
+        QueueJob::Dequeue { prs, token } => {
+            // Top of a stack first, so nothing is left queued on a dequeued parent
+            for pr in prs.iter().rev() {
+                println!("dequeuing pr {} (author changed their mind)", pr);
+                if let Err(e) = dequeue(pr, config, cli, &token) {
+                    eprintln!("could not dequeue pr {}: {}", pr, e);
+                }
+            }
+            Next::Done
+        }
+        QueueJob::Update {
+            pr,
+            branch,
+            force,
+            token,
+        } => {
+            if let Err(e) = update_pull_request(config, &pr, &branch, force, &token, cli.dry_run) {
+                eprintln!("could not update pr {}: {}", pr, e);
+            }
+            Next::Done
+        }
+    }
+}
+
+fn run_delayed_enqueue(job: DelayedEnqueue, config: &Conf, cli: &Cli) -> Next<QueueJob> {
+    let pr = &job.pr.pr;
+    if let Some(deadline) = job.checks_deadline {
+        let waiting_on = match GitHub::pending_required_checks(pr, &job.pr.token) {
+            Ok(pending) if pending.is_empty() => None,
+            Ok(pending) => Some(pending.join(", ")),
+            Err(e) => Some(format!("unknown ({})", e)),
+        };
+        if let Some(waiting_on) = waiting_on {
+            if Instant::now() < deadline {
+                println!("pr {} waiting on required checks: {}", pr, waiting_on);
+                return Next::At(
+                    Instant::now() + CHECKS_POLL_INTERVAL,
+                    QueueJob::Enqueue(Box::new(job)),
+                );
+            }
+            println!(
+                "pr {} required checks not done in time ({}) - enqueuing anyway",
+                pr, waiting_on
+            );
+        }
+    }
+
+    println!("enqueuing pr {}", pr);
+    let mut jobs = match enqueue(
+        pr,
+        config,
+        cli,
+        &job.pr.token,
+        job.as_stack,
+        job.priority.as_deref(),
+        job.pr.enqueue_vars(),
+    ) {
+        Ok(enqueued) => job.after.jobs(&job.pr, &enqueued),
+        Err(e) => {
+            eprintln!("could not enqueue pr {}: {}", pr, e);
+            Vec::new()
+        }
+    };
+
+    // The next stack member goes in `stack.enqueue_interval` after this one actually did, so a
+    // slow checks wait or a long delay sample here can't let it overtake its parent.
+    let mut rest = job.rest.into_iter();
+    if let Some(next) = rest.next() {
+        let mut rng = rand::thread_rng();
+        let delay = config.stack_enqueue_interval()
+            + config.sample_enqueue_delay(&mut rng).unwrap_or_default();
+        println!(
+            "will enqueue pr {} in {}s (next in its stack after {})",
+            next.pr,
+            delay.as_secs(),
+            pr
+        );
+        let (at, next) =
+            DelayedEnqueue::plan(config, cli, &mut rng, next, false, delay, rest.collect());
+        jobs.push((at, QueueJob::Enqueue(Box::new(next))));
+    }
+    Next::All(jobs)
+}
+
+/// Enqueue a freshly created PR, or schedule the enqueue when it should happen later: after a
+/// sampled `enqueue_delay`, or once its required checks finish. Also schedules what happens to
+/// the PR once it is in the queue, and the enqueue of the stack members in `rest` after it.
+/// Failures are logged. Returns the number of jobs handed to `scheduler`.
+fn enqueue_new_pr<'a, R: Rng>(
+    config: &'a Conf,
+    cli: &Cli,
+    scheduler: &QueueScheduler<'a>,
+    rng: &mut R,
+    new_pr: NewPr,
+    as_stack: bool,
+    rest: Vec<NewPr>,
+) -> usize {
+    let delay = config.sample_enqueue_delay(rng).unwrap_or_default();
+    let (at, job) = DelayedEnqueue::plan(config, cli, rng, new_pr, as_stack, delay, rest);
+
+    if delay.is_zero() && job.checks_deadline.is_none() {
+        let jobs = match run_delayed_enqueue(job, config, cli) {
+            Next::Done => Vec::new(),
+            Next::At(at, job) => vec![(at, job)],
+            Next::All(jobs) => jobs,
+        };
+        let scheduled = jobs.len();
+        for (at, job) in jobs {
+            scheduler.schedule(at, (config, job));
+        }
+        return scheduled;
+    }
+
+    println!(
+        "will enqueue pr {} in {}s{}",
+        job.pr.pr,
+        delay.as_secs(),
+        if job.checks_deadline.is_some() {
+            " once its required checks finish"
+        } else {
+            ""
+        }
+    );
+    scheduler.schedule(at, (config, QueueJob::Enqueue(Box::new(job))));
+    1
+}
+
+/// Enqueue the PRs of a stack one after another (shuffled first for `random-order`), each
+/// `stack.enqueue_interval` after the one before it went in.
+fn enqueue_stack_members<'a, R: Rng>(
+    config: &'a Conf,
+    cli: &Cli,
+    scheduler: &QueueScheduler<'a>,
+    rng: &mut R,
+    mut members: Vec<NewPr>,
+) -> usize {
+    if config.stack.enqueue == StackEnqueue::RandomOrder {
+        members.shuffle(rng);
+    }
+    if members.is_empty() {
+        return 0;
+    }
+    let first = members.remove(0);
+    enqueue_new_pr(config, cli, scheduler, rng, first, false, members)
+}
+
+/// Push another change to a queued PR's `branch` with `edit_files_for_pr`: a new commit on top,
+/// or with `force` the head commit amended and force-pushed. Works in a temporary worktree so
+/// it does not disturb PR generation in the main checkout, and then adds the targets the new
+/// edits impact to the PR body's `deps=[...]`.
+fn update_pull_request(
+    config: &Conf,
+    pr: &str,
+    branch: &str,
+    force: bool,
+    token: &str,
+    dry_run: bool,
+) -> Result<(), String> {
+    // Rewriting a stack member's history would leave the PRs stacked on it based on a commit that
+    // is gone, which no real author does without restacking. Those get a new commit instead.
+    let force = force && (dry_run || !has_stacked_prs(pr, branch, token));
+    let action = if force {
+        "force-pushing"
+    } else {
+        "pushing a new commit to"
+    };
+    if dry_run {
+        println!("dry-run set - not {} pr {} ({})", action, pr, branch);
+        return Ok(());
+    }
+    println!("{} pr {} ({})", action, pr, branch);
+
+    let words = {
+        // fetch and worktree add/remove write the shared repository's refs and metadata
+        let _guard = lock_git();
+        try_git(&["fetch", "origin", branch])?;
+        let worktree = env::temp_dir().join(format!("mq-update-{}-{}", pr, std::process::id()));
+        let worktree = worktree.to_string_lossy().into_owned();
+        try_git(&[
+            "worktree",
+            "add",
+            "--detach",
+            &worktree,
+            &format!("origin/{}", branch),
+        ])?;
+        let result = push_update(config, pr, branch, force, &worktree);
+        let _ = try_git(&["worktree", "remove", "--force", &worktree]);
+        result?
+    };
+    update_deps(pr, &words, token)
+}
+
+/// Whether other PRs are stacked on `pr` (or it can't be told), so its history must not be
+/// rewritten.
+fn has_stacked_prs(pr: &str, branch: &str, token: &str) -> bool {
+    let children = match GitHub::stacked_on(branch, token) {
+        Ok(children) if children.is_empty() => return false,
+        Ok(children) => children.join(", "),
+        Err(e) => format!("unknown ({})", e),
+    };
+    println!(
+        "pr {} has prs stacked on it: {} - pushing a new commit instead of force-pushing",
+        pr, children
+    );
+    true
+}
+
+/// Add the targets edits to `words` impact to a PR body's `deps=[...]`.
+fn update_deps(pr: &str, words: &[String], token: &str) -> Result<(), String> {
+    let body = GitHub::get_pr_body(pr, token)?;
+    match body_with_deps(&body, words) {
+        Some(body) => {
+            println!("pr {} now impacts [{}]", pr, get_targets(&body).join(","));
+            GitHub::edit_body(pr, &body, token).map(|_| ())
+        }
+        None => Ok(()),
+    }
+}
+
+/// `body` with the first letters of `words` added to its `deps=[...]`, or None when it already
+/// lists them all. The earlier edits are still part of the PR, so its existing targets stay.
+fn body_with_deps(body: &str, words: &[String]) -> Option<String> {
+    let mut deps = get_targets(body);
+    let added: Vec<String> = first_letters(words)
+        .into_iter()
+        .filter(|letter| !deps.contains(letter))
+        .collect();
+    if added.is_empty() {
+        return None;
+    }
+    deps.extend(added);
+    let deps_line = format!("deps=[{}]", deps.join(","));
+    Some(if body.contains("deps=[") {
+        let re = Regex::new(r"deps=\[.*?\]").unwrap();
+        re.replace(body, regex::NoExpand(&deps_line)).into_owned()
+    } else {
+        format!("{}\n\n{}\n", body, deps_line)
+    })
+}
+
+/// Sorted, distinct first letters of `words`: the targets edits to them impact.
+fn first_letters(words: &[String]) -> Vec<String> {
+    let mut letters: Vec<String> = words
+        .iter()
+        .filter_map(|word| word.chars().next())
+        .map(|c| c.to_string())
+        .collect();
+    letters.sort();
+    letters.dedup();
+    letters
+}
+
+/// Serialises use of the main checkout's git repository between PR generation and the queue
+/// jobs that push updates from the scheduler thread.
+static GIT_LOCK: Mutex<()> = Mutex::new(());
 
-    Ok((pr_number.to_string(), deps_count, branch_name))
+fn lock_git() -> std::sync::MutexGuard<'static, ()> {
+    // A panicking window (caught by the daemon) leaves nothing half-done that the lock guards
+    GIT_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
-fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
+fn push_update(
+    config: &Conf,
+    pr: &str,
+    branch: &str,
+    force: bool,
+    worktree: &str,
+) -> Result<Vec<String>, String> {
+    let git_in_worktree = |args: &[&str]| {
+        let mut argv = vec!["-C", worktree];
+        argv.extend_from_slice(args);
+        try_git(&argv)
+    };
+
+    let mut filenames: Vec<String> = get_txt_files(Path::new(worktree), config)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    filenames.sort();
+
+    // The PR's own number, so it touches as many deps as it did when it was created
+    let words = edit_files_for_pr(&filenames, pr.parse().unwrap_or_default(), config);
+    for filename in &filenames {
+        let _ = git_in_worktree(&["add", filename]);
+    }
+
+    let commit_msg = format!("Moving words {}", words.join(", "));
+    let mut commit = vec!["commit", "--no-verify", "-m", &commit_msg];
+    if force {
+        commit.push("--amend");
+    }
+    git_in_worktree(&commit).map_err(|e| format!("Failed to commit changes: {}", e))?;
+
+    let refspec = format!("HEAD:refs/heads/{}", branch);
+    let mut push = vec!["push", "origin", &refspec];
+    if force {
+        push.push("--force");
+    }
+    git_in_worktree(&push)?;
+    Ok(words)
+}
+
+/// Put the checkout back on the first protected branch with a clean tree, after a window that
+/// may have stopped halfway through building a stack-change/... branch.
+fn reset_git_state(config: &Conf) {
+    let _guard = lock_git();
+    let base = &config.pullrequest.protected_branches[0];
+    let steps: [&[&str]; 2] = [&["reset", "--hard"], &["clean", "-fd"]];
+    for step in steps {
+        if let Err(e) = try_git(step) {
+            eprintln!("warning: git {} failed: {}", step.join(" "), e);
+        }
+    }
+    match checkout_branch(base) {
+        Ok(()) => {
+            if let Err(e) = try_git(&["reset", "--hard", &format!("origin/{}", base)]) {
+                eprintln!("warning: could not reset '{}' to origin: {}", base, e);
+            }
+        }
+        Err(e) => eprintln!("warning: could not check out '{}': {}", base, e),
+    }
+}
+
+/// PRs created by one generate window, the last PR number it saw and the queue jobs it
+/// scheduled.
+struct WindowOutcome {
+    created: usize,
+    last_pr: u32,
+    scheduled: usize,
+}
+
+/// One generate window: a burst of `requests_per_run` PRs, or `requests_per_hour` spread over
+/// `run_generate_for`. `last_pr` carries the PR number over from a previous window; None looks
+/// it up on GitHub. Delayed enqueues, cancellations and updates go to `scheduler`, which
+/// outlives the window. Stops early once a shutdown is requested.
+fn generate_window<'a>(
+    config: &'a Conf,
+    cli: &Cli,
+    scheduler: &QueueScheduler<'a>,
+    last_pr: Option<u32>,
+    pause_during_setup: bool,
+) -> anyhow::Result<WindowOutcome> {
     if config.is_generator_disabled() {
         println!("generator is disabled pull requests per hour is set to 0");
-        return Ok(());
+        return Ok(WindowOutcome {
+            created: 0,
+            last_pr: last_pr.unwrap_or_default(),
+            scheduled: 0,
+        });
     }
 
     configure_git(config);
@@ -565,7 +1687,7 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
         eprintln!("No GitHub tokens provided. Use --gh-token to specify at least one token.");
         std::process::exit(1);
     }
-    let mut last_pr = get_last_pr(&github_tokens[0]);
+    let mut last_pr = last_pr.unwrap_or_else(|| get_last_pr(&github_tokens[0]));
 
     let mut prs: Vec<String> = Vec::new();
 
@@ -590,6 +1712,18 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
         );
     }
 
+    let paused = if !pause_during_setup {
+        None
+    } else if config.pullrequest.requests_per_run == 0 {
+        println!("--pause-during-setup only applies in burst mode (requests_per_run) - ignoring");
+        None
+    } else {
+        Some(PausedQueues::pause(config, cli)?)
+    };
+
+    let mut rng = rand::thread_rng();
+    let mut pairs = PairPlanner::default();
+    let mut scheduled = 0usize;
     let mut pr_index = 0usize;
     for (stack_index, depth) in stack_plan.iter().enumerate() {
         let protected_branches = &config.pullrequest.protected_branches;
@@ -602,6 +1736,8 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
 
         // 5-char [a-z0-9] id shared by every PR branch in this stack (~60M possibilities).
         let stack_id = new_stack_id();
+        // PRs created so far in this stack, for the enqueue strategies that wait for all of them
+        let mut stack_members: Vec<NewPr> = Vec::new();
 
         println!(
             "stack {} ({}) of {}: depth {} — first PR will target '{}'",
@@ -613,6 +1749,9 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
         );
 
         for position in 1..=*depth {
+            if shutdown::requested() {
+                break;
+            }
             // Defensive: the first PR in every stack must target the protected branch for this
             // stack, never the previous stack's tip. If this ever fires, something regressed.
             if position == 1 && current_base != protected_base {
@@ -623,7 +1762,8 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
                 current_base = protected_base.clone();
             }
             let start = Instant::now();
-            let files = get_txt_files(config)?;
+            let _guard = lock_git();
+            let files = get_txt_files(&env::current_dir()?, config)?;
             let mut filenames: Vec<String> = files
                 .into_iter()
                 .map(|path| path.to_string_lossy().into_owned())
@@ -651,7 +1791,13 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
                 stack_parent_pr_number,
                 &stack_id,
                 position,
+                pairs.plan(
+                    last_pr + 1,
+                    &stack_id,
+                    config.pullrequest.pairwise_conflict_every,
+                ),
             );
+            drop(_guard);
             if pr_result.is_err() {
                 println!("problem created pr for files: {:?}", filenames);
                 // Abort the rest of this stack: without this PR's branch,
@@ -659,7 +1805,15 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
                 break;
             }
             let duration = start.elapsed();
-            let (pr, deps_count, head_branch) = pr_result.unwrap();
+            let CreatedPr {
+                pr,
+                deps_count,
+                head_branch,
+                deps,
+                pair,
+            } = pr_result.unwrap();
+            // Only now that the PR exists does it take its side of a pair
+            pairs.created(pair, &stack_id);
             let stack_tag = if *depth > 1 {
                 format!(" [stack {}/{}]", position, *depth)
             } else {
@@ -674,21 +1828,6 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
                 duration.as_secs(),
                 (pull_request_every as f32 / 60.0)
             );
-            thread::sleep(Duration::from_secs(pull_request_every) / 2);
-            // For stacks, only enqueue the top PR (position == depth). Lower
-            // PRs can't merge on their own until the tip is resolved, so
-            // enqueueing them just churns the queue.
-            let is_top_of_stack = position == *depth;
-            if is_top_of_stack {
-                let as_stack = *depth > 1;
-                enqueue(&pr, config, cli, current_token, as_stack);
-            } else {
-                println!(
-                    "skipping enqueue for pr {} (stack {}/{})",
-                    pr, position, *depth
-                );
-            }
-            thread::sleep(Duration::from_secs(pull_request_every) / 2);
             // Keep in sync with GitHub's assigned number for the next `last_pr + 1` edit sequence.
             if let Ok(n) = pr.parse::<u32>() {
                 last_pr = n;
@@ -697,15 +1836,94 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
                 last_pr = last_pr.saturating_add(1);
                 stack_parent_pr_number = None;
             }
-            prs.push(pr);
+            prs.push(pr.clone());
             pr_index += 1;
 
+            // Nothing more is enqueued once a shutdown is requested
+            if !shutdown::sleep(Duration::from_secs(pull_request_every) / 2) {
+                break;
+            }
+            let new_pr = NewPr {
+                pr: pr.clone(),
+                token: current_token.clone(),
+                base_branch: current_base.clone(),
+                head_branch: head_branch.clone(),
+                stack_id: stack_id.clone(),
+                deps,
+            };
+            let is_top_of_stack = position == *depth;
+            match config.stack.enqueue {
+                StackEnqueue::Each => {
+                    scheduled +=
+                        enqueue_new_pr(config, cli, scheduler, &mut rng, new_pr, false, Vec::new())
+                }
+                // For stacks, only enqueue the top PR (position == depth). Lower
+                // PRs can't merge on their own until the tip is resolved, so
+                // enqueueing them just churns the queue.
+                StackEnqueue::Top if is_top_of_stack => {
+                    scheduled += enqueue_new_pr(
+                        config,
+                        cli,
+                        scheduler,
+                        &mut rng,
+                        new_pr,
+                        *depth > 1,
+                        Vec::new(),
+                    )
+                }
+                StackEnqueue::Top => println!(
+                    "skipping enqueue for pr {} (stack {}/{})",
+                    pr, position, *depth
+                ),
+                StackEnqueue::BottomUp | StackEnqueue::RandomOrder => {
+                    stack_members.push(new_pr);
+                    if is_top_of_stack {
+                        scheduled += enqueue_stack_members(
+                            config,
+                            cli,
+                            scheduler,
+                            &mut rng,
+                            std::mem::take(&mut stack_members),
+                        );
+                    } else {
+                        println!(
+                            "deferring enqueue for pr {} until its stack is complete (stack {}/{})",
+                            pr, position, *depth
+                        );
+                    }
+                }
+            }
+            if !shutdown::sleep(Duration::from_secs(pull_request_every) / 2) {
+                break;
+            }
+
             // Next PR in this stack bases on the branch we just pushed.
             current_base = head_branch;
         }
 
+        // A stack cut short by a failed create still gets the PRs it has into the queue
+        if !stack_members.is_empty() {
+            let members: Vec<&str> = stack_members.iter().map(|m| m.pr.as_str()).collect();
+            if shutdown::requested() {
+                // Same as any other PR: nothing is enqueued once a shutdown is requested
+                println!(
+                    "shutting down - not enqueuing stack {} prs {}",
+                    stack_id,
+                    members.join(", ")
+                );
+            } else {
+                println!(
+                    "stack {} is incomplete - enqueuing the prs it has: {}",
+                    stack_id,
+                    members.join(", ")
+                );
+                scheduled += enqueue_stack_members(config, cli, scheduler, &mut rng, stack_members);
+            }
+        }
+
         // Leave git on the protected branch after each stack so the next stack always forks from
         // a known base, even if the user started generate from another local branch.
+        let _guard = lock_git();
         if let Err(e) = checkout_branch(&protected_base) {
             eprintln!(
                 "warning: could not check out protected branch '{}' after stack: {}",
@@ -714,11 +1932,256 @@ fn generate(config: &Conf, cli: &Cli) -> anyhow::Result<()> {
         } else {
             let _ = try_git(&["pull"]);
         }
+        if shutdown::requested() {
+            break;
+        }
     }
 
+    // Let the whole burst into the queue at once
+    drop(paused);
+
+    Ok(WindowOutcome {
+        created: prs.len(),
+        last_pr,
+        scheduled,
+    })
+}
+
+/// Delay before retrying after `failures` consecutive failed windows: 10s doubling to 10 minutes.
+fn daemon_backoff(failures: u32) -> Duration {
+    let secs = 10u64.saturating_mul(1 << failures.saturating_sub(1).min(6));
+    Duration::from_secs(secs.min(600))
+}
+
+/// `generate --daemon`: run generate windows back to back until SIGTERM/SIGINT, so arrivals are
+/// not shaped by cron granularity or workflow start-up. `last_pr` is carried between windows and
+/// re-read from GitHub after a failure. A failed window (including a panic in a git or gh call)
+/// is logged, the checkout reset to a clean protected branch, and retried with backoff. A background thread keeps the heartbeat file fresh.
+fn generate_daemon<'a>(
+    config: &'a Conf,
+    cli: &Cli,
+    args: &Generate,
+    scheduler: &QueueScheduler<'a>,
+) -> anyhow::Result<()> {
+    if config.is_generator_disabled() {
+        return Err(anyhow::anyhow!(
+            "generator is disabled - set requests_per_hour or requests_per_run to run as a daemon"
+        ));
+    }
+    let interval = parse_duration::parse(&args.heartbeat_interval).map_err(|e| {
+        anyhow::anyhow!(
+            "invalid --heartbeat-interval '{}': {:?}",
+            args.heartbeat_interval,
+            e
+        )
+    })?;
+    let heartbeat_path = PathBuf::from(&args.heartbeat);
+    shutdown::install_handler();
+
+    let state = Mutex::new(HeartbeatState::new());
+    let done = AtomicBool::new(false);
+    let write_heartbeat = |state: &HeartbeatState| {
+        if let Err(e) = heartbeat::write(&heartbeat_path, state) {
+            eprintln!(
+                "warning: could not write heartbeat {}: {}",
+                heartbeat_path.display(),
+                e
+            );
+        }
+    };
+    let update = |f: &dyn Fn(&mut HeartbeatState)| {
+        let mut state = state.lock().unwrap();
+        f(&mut state);
+        write_heartbeat(&state);
+    };
+
+    println!(
+        "generate daemon started (pid {}, heartbeat {})",
+        std::process::id(),
+        heartbeat_path.display()
+    );
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut last_write = Instant::now();
+            while !done.load(Ordering::SeqCst) {
+                if last_write.elapsed() >= interval {
+                    write_heartbeat(&state.lock().unwrap());
+                    last_write = Instant::now();
+                }
+                thread::sleep(Duration::from_millis(250));
+            }
+        });
+
+        let mut last_pr: Option<u32> = None;
+        let mut failures = 0u32;
+        while !shutdown::requested() {
+            update(&|s| s.status = "running".to_string());
+            let started = Instant::now();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                generate_window(config, cli, scheduler, last_pr, args.pause_during_setup)
+            }));
+            let error = match result {
+                Ok(Ok(outcome)) => {
+                    failures = 0;
+                    last_pr = Some(outcome.last_pr);
+                    update(&|s| {
+                        s.windows += 1;
+                        s.prs_created += outcome.created as u64;
+                        s.last_pr = Some(outcome.last_pr);
+                        s.last_success_at = Some(Utc::now().to_rfc3339());
+                        s.consecutive_failures = 0;
+                    });
+                    None
+                }
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(payload) => Some(panic_message(payload.as_ref())),
+            };
+
+            if let Some(error) = error {
+                failures += 1;
+                last_pr = None;
+                reset_git_state(config);
+                let backoff = daemon_backoff(failures);
+                eprintln!(
+                    "generate window failed ({} in a row): {} - retrying in {}s",
+                    failures,
+                    error,
+                    backoff.as_secs()
+                );
+                update(&|s| {
+                    s.status = "backing-off".to_string();
+                    s.consecutive_failures = failures;
+                    s.last_error = Some(error.clone());
+                });
+                shutdown::sleep(backoff);
+                continue;
+            }
+
+            // Burst windows end as soon as their PRs exist; start the next one run_generate_for
+            // after this one started.
+            if let Some(rest) = config
+                .run_generate_for_duration()
+                .checked_sub(started.elapsed())
+            {
+                shutdown::sleep(rest);
+            }
+        }
+        done.store(true, Ordering::SeqCst);
+    });
+
+    update(&|s| s.status = "stopped".to_string());
+    println!("generate daemon stopped");
     Ok(())
 }
 
+/// `mq run-scenario`: run each phase's `generate` in turn, recording phase boundaries in the
+/// ledger. Every phase config is loaded and validated before the first phase starts.
+fn run_scenario(args: &RunScenario, cli: &Cli) -> anyhow::Result<()> {
+    let scenario = Scenario::from_file(&args.path).map_err(|e| anyhow::anyhow!(e))?;
+    let mut phases = Vec::new();
+    for phase in &scenario.phases {
+        let config = phase.config().map_err(|e| anyhow::anyhow!(e))?;
+        config
+            .is_valid(Some(cli))
+            .map_err(|e| anyhow::anyhow!("phase '{}': {}", phase.name, e))?;
+        let duration = phase.duration().map_err(|e| anyhow::anyhow!(e))?;
+        phases.push((phase, config, duration));
+    }
+
+    let ledger = Ledger::new(
+        args.ledger
+            .as_deref()
+            .or(scenario.ledger.as_deref())
+            .unwrap_or(DEFAULT_LEDGER),
+    );
+    println!(
+        "running {} phases from {} (ledger: {})",
+        phases.len(),
+        args.path,
+        ledger.path().display()
+    );
+    let record = |event: &str, fields: Value| {
+        if let Err(e) = ledger.record(event, fields) {
+            eprintln!("warning: could not write to ledger: {}", e);
+        }
+    };
+
+    // Ctrl-C or SIGTERM ends the current phase early, so the ledger still gets its closing entries
+    shutdown::install_handler();
+    // Queue jobs planned in one phase keep running into the next ones
+    thread::scope(|scope| {
+        let scheduler = &spawn_queue_scheduler(scope, cli);
+        record(
+            "scenario_start",
+            serde_json::json!({ "scenario": args.path, "phases": phases.len() }),
+        );
+        let mut interrupted = false;
+        for (index, (phase, config, duration)) in phases.iter().enumerate() {
+            if shutdown::requested() {
+                interrupted = true;
+                break;
+            }
+            println!(
+                "phase {}/{} '{}' for {}",
+                index + 1,
+                phases.len(),
+                phase.name,
+                phase.duration
+            );
+            let fields = serde_json::json!({
+                "scenario": args.path,
+                "phase": phase.name,
+                "index": index,
+                "duration": phase.duration,
+                "overrides": phase.override_table(),
+            });
+            record("phase_start", fields.clone());
+
+            let started = Instant::now();
+            let result = generate_window(config, cli, scheduler, None, false).map(|_| ());
+            if let Err(ref e) = result {
+                let mut failed = fields;
+                failed["error"] = Value::String(e.to_string());
+                record("phase_end", failed);
+                record(
+                    "scenario_end",
+                    serde_json::json!({ "scenario": args.path, "error": e.to_string() }),
+                );
+                scheduler.stop();
+                return result;
+            }
+            // Burst phases (and slow distributed ones) finish early; hold the phase for its duration
+            let mut completed = !shutdown::requested();
+            if let Some(remaining) = duration
+                .checked_sub(started.elapsed())
+                .filter(|_| completed)
+            {
+                println!(
+                    "phase '{}' done generating - waiting {}s for the phase to end",
+                    phase.name,
+                    remaining.as_secs()
+                );
+                completed = shutdown::sleep(remaining);
+            }
+            if !completed {
+                let mut cut_short = fields;
+                cut_short["interrupted"] = Value::Bool(true);
+                record("phase_end", cut_short);
+                interrupted = true;
+                break;
+            }
+            record("phase_end", fields);
+        }
+        let mut end = serde_json::json!({ "scenario": args.path });
+        if interrupted {
+            println!("shutting down - scenario stopped early");
+            end["interrupted"] = Value::Bool(true);
+        }
+        record("scenario_end", end);
+        Ok(())
+    })
+}
+
 #[cfg(test)]
 mod stack_branch_tests {
     use super::{head_branch_for_stack, new_stack_id};
@@ -739,6 +2202,33 @@ mod stack_branch_tests {
     }
 }
 
+#[cfg(test)]
+mod update_tests {
+    use super::body_with_deps;
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn adds_new_targets_and_keeps_existing_ones() {
+        let body = "moving words\n\ndeps=[a,c,logical-conflicts]\n\n[stack]\n";
+        assert_eq!(
+            body_with_deps(body, &words(&["bat", "apple", "dog"])).as_deref(),
+            Some("moving words\n\ndeps=[a,c,logical-conflicts,b,d]\n\n[stack]\n")
+        );
+        assert_eq!(body_with_deps(body, &words(&["cat", "ant"])), None);
+    }
+
+    #[test]
+    fn appends_deps_to_a_body_without_them() {
+        assert_eq!(
+            body_with_deps("hand edited", &words(&["egg"])).as_deref(),
+            Some("hand edited\n\ndeps=[e]\n")
+        );
+    }
+}
+
 fn run() -> anyhow::Result<()> {
     let cli: Cli = Cli::parse();
 
@@ -747,7 +2237,7 @@ fn run() -> anyhow::Result<()> {
         return Ok(());
     }
 
-    if let Some(Subcommands::Defaultconfig {}) = &cli.subcommand {
+    if let Some(Subcommands::Defaultconfig) = &cli.subcommand {
         Conf::print_default();
         return Ok(());
     }
@@ -765,14 +2255,26 @@ fn run() -> anyhow::Result<()> {
     });
 
     match &cli.subcommand {
-        Some(Subcommands::Housekeeping {}) => {
+        Some(Subcommands::Housekeeping(hk)) => {
             let token: String = get_first_github_token(&cli);
-            housekeeping(&config, &token);
+            if hk.watch {
+                let interval = parse_duration::parse(&hk.interval).map_err(|e| {
+                    anyhow::anyhow!("invalid --interval '{}': {:?}", hk.interval, e)
+                })?;
+                if interval.is_zero() {
+                    return Err(anyhow::anyhow!("--interval must be greater than zero"));
+                }
+                watch_housekeeping(&config, &token, interval);
+            } else {
+                housekeeping(&config, &token, &mut HashSet::new())
+                    .map_err(|e| anyhow::anyhow!(e))?;
+            }
             Ok(())
         }
-        Some(Subcommands::TestSim {}) => {
-            if !simulate_test(&config) {
-                std::process::exit(1);
+        Some(Subcommands::TestSim(args)) => {
+            let exit_code = simulate_test(config, args, &cli);
+            if exit_code != 0 {
+                std::process::exit(exit_code);
             }
             Ok(())
         }
@@ -819,16 +2321,47 @@ fn run() -> anyhow::Result<()> {
             }
             Ok(())
         }
-        Some(Subcommands::Generate {}) => generate(&config, &cli),
+        Some(Subcommands::Generate(args)) => generate(&config, &cli, args),
         Some(Subcommands::UploadTargets(ut)) => {
             // upload_targets(&cli, &gen::pullrequest::get_json()); // &ut.github_json);
             upload_targets(&config, &cli, &ut.github_json);
             Ok(())
         }
+        Some(Subcommands::Simulate(args)) => run_simulation(&config, args),
+        Some(Subcommands::Sweep(args)) => run_parameter_sweep(&config, args),
+        Some(Subcommands::RunScenario(args)) => run_scenario(args, &cli),
         Some(Subcommands::Enqueue(enqueue_args)) => {
             println!("Enqueuing PR: {}", enqueue_args.pr);
             let token = get_first_github_token(&cli);
-            enqueue(&enqueue_args.pr, &config, &cli, &token, false);
+            let priority = enqueue_args.priority.as_deref();
+            let vars = EnqueueVars::default();
+            if let Err(e) = enqueue(
+                &enqueue_args.pr,
+                &config,
+                &cli,
+                &token,
+                false,
+                priority,
+                vars,
+            ) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(Subcommands::Queue(queue)) => match &queue.command {
+            QueueCommand::Status(args) => queue_status(&config, &cli, args),
+            QueueCommand::Pause(args) => set_queue(&config, &cli, args, QueueState::Paused),
+            QueueCommand::Drain(args) => set_queue(&config, &cli, args, QueueState::Draining),
+            QueueCommand::Resume(args) => set_queue(&config, &cli, args, QueueState::Running),
+        },
+        Some(Subcommands::Dequeue(dequeue_args)) => {
+            println!("Dequeuing PR: {}", dequeue_args.pr);
+            let token = get_first_github_token(&cli);
+            if let Err(e) = dequeue(&dequeue_args.pr, &config, &cli, &token) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
             Ok(())
         }
         _ => {
@@ -849,3 +2382,18 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod daemon_tests {
+    use super::daemon_backoff;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_doubles_up_to_ten_minutes() {
+        assert_eq!(daemon_backoff(1), Duration::from_secs(10));
+        assert_eq!(daemon_backoff(2), Duration::from_secs(20));
+        assert_eq!(daemon_backoff(4), Duration::from_secs(80));
+        assert_eq!(daemon_backoff(7), Duration::from_secs(600));
+        assert_eq!(daemon_backoff(50), Duration::from_secs(600));
+    }
+}
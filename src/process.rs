@@ -1,4 +1,7 @@
-use std::process::Command;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 fn exec(cmd: &str, args: &[&str]) -> Result<String, String> {
     exec_with_env(cmd, args, None)
@@ -36,21 +39,77 @@ fn exec_with_env_quiet(
             eprintln!("stderr: {}", String::from_utf8_lossy(&output.stderr));
             eprintln!("Call to {} {} failed", cmd, args.join(" "));
         }
-        return Err(String::from_utf8_lossy(&output.stderr)
+        Err(String::from_utf8_lossy(&output.stderr)
             .into_owned()
             .trim()
-            .to_string());
+            .to_string())
     } else {
-        return Ok(String::from_utf8_lossy(&output.stdout)
+        Ok(String::from_utf8_lossy(&output.stdout)
             .into_owned()
             .trim()
-            .to_string());
+            .to_string())
     }
 }
 
-pub fn run_cmd(cmd: &str) -> String {
-    let args: Vec<&str> = cmd.split_whitespace().collect();
-    exec(args.first().unwrap(), &args[1..]).expect("run failed")
+/// Run `argv` with extra environment variables, killing it after `timeout`. Returns stdout, or
+/// an error describing why the command could not start, timed out or failed.
+pub fn run_with_timeout(
+    argv: &[String],
+    env_vars: &[(String, String)],
+    timeout: Duration,
+) -> Result<String, String> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| "empty command".to_string())?;
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(env_vars.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not start {}: {}", program, e))?;
+
+    // Drain the pipes while waiting so a chatty command cannot block on a full pipe
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} timed out after {:?}", program, timeout));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(50)),
+            Err(e) => return Err(format!("could not wait for {}: {}", program, e)),
+        }
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if status.success() {
+        Ok(stdout.trim().to_string())
+    } else {
+        Err(format!(
+            "{} failed ({}): {}",
+            program,
+            status,
+            stderr.trim()
+        ))
+    }
+}
+
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut output);
+        }
+        output
+    })
 }
 
 pub fn try_gh(args: &[&str], token: &str) -> Result<String, String> {
@@ -68,3 +127,43 @@ pub fn try_git(args: &[&str]) -> Result<String, String> {
 pub fn try_git_quiet(args: &[&str]) -> Result<String, String> {
     exec_with_env_quiet("git", args, None, true)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_run_with_timeout_passes_args_and_env() {
+        let env = vec![("MQ_PR_NUMBER".to_string(), "42".to_string())];
+        let out = run_with_timeout(
+            &argv(&["sh", "-c", "echo \"$1 $MQ_PR_NUMBER\"", "sh", "two words"]),
+            &env,
+            Duration::from_secs(10),
+        );
+        assert_eq!(out, Ok("two words 42".to_string()));
+    }
+
+    #[test]
+    fn test_run_with_timeout_errors() {
+        let failed = run_with_timeout(
+            &argv(&["sh", "-c", "echo oops >&2; exit 3"]),
+            &[],
+            Duration::from_secs(10),
+        );
+        assert!(failed.unwrap_err().contains("oops"));
+
+        let start = Instant::now();
+        let timed_out = run_with_timeout(&argv(&["sleep", "5"]), &[], Duration::from_millis(200));
+        assert!(timed_out.unwrap_err().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        assert!(
+            run_with_timeout(&argv(&["mq-no-such-program"]), &[], Duration::from_secs(1)).is_err()
+        );
+        assert!(run_with_timeout(&[], &[], Duration::from_secs(1)).is_err());
+    }
+}
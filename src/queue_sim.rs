@@ -0,0 +1,797 @@
+//! Offline discrete-event simulation of a merge queue.
+//!
+//! PRs arrive at the rate configured for `generate` (`requests_per_hour`, or `requests_per_run`
+//! every 10 minutes), shaped by `deps_distribution`, `stacks_distribution` and the logical
+//! conflict settings, and stacks are enqueued the way `stack.enqueue` tells `generate` to. Each
+//! test run fails and takes as long as `test-sim` would for the same impacted targets. The queue
+//! itself is modelled from `QueueParams`: batches of PRs are tested speculatively on top of the
+//! batches ahead of them, failed batches are retried, bisected and finally ejected, and
+//! everything tested on top of an ejected batch is restarted.
+
+use crate::config::{Conf, StackEnqueue};
+use crate::conflict::PairPlanner;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::time::Duration;
+
+/// `generate` is assumed to run every 10 minutes when `requests_per_run` is used.
+const GENERATE_EVERY_SECS: f64 = 600.0;
+
+/// The queue is given this many arrival windows in total to drain before the run stops;
+/// whatever is left is reported as still queued.
+const DRAIN_FACTOR: f64 = 3.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueParams {
+    /// Only PRs with overlapping impacted targets are tested on top of each other
+    pub parallel: bool,
+    /// Maximum PRs tested together in one batch
+    pub batch_size: usize,
+    /// Maximum batches testing at once
+    pub concurrency: usize,
+    /// Merge a failed batch when a batch tested on top of it passes
+    pub optimistic: bool,
+    /// Re-tests of a failed batch before it is bisected or ejected
+    pub retries: u32,
+    /// How long PRs keep arriving
+    #[serde(rename = "duration_secs", serialize_with = "serialize_secs")]
+    pub duration: Duration,
+    /// Number of distinct targets PR dependencies are drawn from
+    pub targets: usize,
+    pub seed: u64,
+}
+
+fn serialize_secs<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u64(d.as_secs())
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SimulationReport {
+    pub arrived: usize,
+    pub merged: usize,
+    pub failed: usize,
+    pub queued: usize,
+    pub simulated_hours: f64,
+    pub throughput_per_hour: f64,
+    pub latency_mean_mins: f64,
+    pub latency_p50_mins: f64,
+    pub latency_p90_mins: f64,
+    pub latency_p99_mins: f64,
+    pub test_runs: usize,
+    /// Runs thrown away because a batch they were tested on top of was ejected
+    pub restarted_runs: usize,
+    pub flaky_failures: usize,
+    pub retries: usize,
+    pub bisections: usize,
+    pub optimistic_merges: usize,
+    pub ci_hours: f64,
+    pub max_queue_depth: usize,
+}
+
+struct SimPr {
+    number: u32,
+    arrival: f64,
+    /// When the PR joins the queue; None for stack members only merged through their tip
+    enqueued: Option<f64>,
+    targets: BTreeSet<String>,
+    parent: Option<usize>,
+    /// Side of a pairwise logical conflict this PR writes, as `(pair_id, side)`
+    pair: Option<(u32, char)>,
+    merged_at: Option<f64>,
+    failed: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BatchState {
+    Ready,
+    Testing {
+        started: f64,
+        ends: f64,
+        passed: bool,
+    },
+    Done {
+        passed: bool,
+    },
+}
+
+struct Batch {
+    prs: Vec<usize>,
+    state: BatchState,
+    /// Batches (still queued when the run started) whose changes this run was tested on top of
+    tested_on: Vec<usize>,
+    attempts: u32,
+}
+
+struct Simulation<'a> {
+    config: &'a Conf,
+    params: &'a QueueParams,
+    rng: StdRng,
+    prs: Vec<SimPr>,
+    batches: Vec<Batch>,
+    queue: Vec<usize>,
+    /// PRs in the order they are enqueued
+    enqueue_order: Vec<usize>,
+    waiting: VecDeque<usize>,
+    /// Pairwise conflict sides (`pair_id`, `side`) already merged into the target branch
+    merged_pairs: BTreeSet<(u32, char)>,
+    report: SimulationReport,
+    ci_secs: f64,
+}
+
+/// Run the simulation for `config` and `params`.
+pub fn simulate_queue(config: &Conf, params: &QueueParams) -> Result<SimulationReport, String> {
+    if params.batch_size == 0 {
+        return Err("batch size must be at least 1".to_string());
+    }
+    if params.concurrency == 0 {
+        return Err("concurrency must be at least 1".to_string());
+    }
+    if params.targets == 0 {
+        return Err("targets must be at least 1".to_string());
+    }
+
+    let mut sim = Simulation::new(config, params)?;
+    sim.run();
+    Ok(sim.report)
+}
+
+/// Generated PRs in arrival order. Stack members arrive together, each on top of the previous,
+/// and are enqueued according to `stack.enqueue`.
+fn arrivals(config: &Conf, params: &QueueParams, rng: &mut StdRng) -> Result<Vec<SimPr>, String> {
+    let window = params.duration.as_secs_f64();
+    // (arrival time, stack depth)
+    let mut stacks: Vec<(f64, usize)> = Vec::new();
+    if config.pullrequest.requests_per_hour > 0 {
+        // A Poisson process conditioned on its count places arrivals uniformly over the window.
+        let total = (config.pullrequest.requests_per_hour as f64 * window / 3600.0).round();
+        let mut depths = config.plan_stacks(total as usize);
+        depths.shuffle(rng);
+        stacks.extend(depths.into_iter().map(|d| (rng.gen_range(0.0..window), d)));
+        stacks.sort_by(|a, b| a.0.total_cmp(&b.0));
+    } else if config.pullrequest.requests_per_run > 0 {
+        let mut at = 0.0;
+        while at < window {
+            let mut depths = config.plan_stacks(config.pullrequest.requests_per_run as usize);
+            depths.shuffle(rng);
+            stacks.extend(depths.into_iter().map(|d| (at, d)));
+            at += GENERATE_EVERY_SECS;
+        }
+    } else {
+        return Err(
+            "set pullrequest.requests_per_hour or requests_per_run to simulate".to_string(),
+        );
+    }
+
+    let universe: Vec<String> = (0..params.targets).map(target_name).collect();
+    let mut prs: Vec<SimPr> = Vec::new();
+    let mut pairs = PairPlanner::default();
+    for (stack, (arrival, depth)) in stacks.into_iter().enumerate() {
+        let mut parent: Option<usize> = None;
+        let mut order: Vec<usize> = (0..depth).collect();
+        if config.stack.enqueue == StackEnqueue::RandomOrder {
+            order.shuffle(rng);
+        }
+        for position in 0..depth {
+            let enqueued = match config.stack.enqueue {
+                StackEnqueue::Top if position + 1 < depth => None,
+                StackEnqueue::Top | StackEnqueue::Each => Some(arrival),
+                StackEnqueue::BottomUp | StackEnqueue::RandomOrder => Some(
+                    arrival
+                        + config.stack_enqueue_interval().as_secs_f64()
+                            * order.iter().position(|&p| p == position).unwrap() as f64,
+                ),
+            };
+            let number = prs.len() as u32 + 1;
+            let count = config
+                .get_dependency_count(number, universe.len())
+                .clamp(1, universe.len());
+            let mut targets: BTreeSet<String> =
+                universe.choose_multiple(rng, count).cloned().collect();
+            let stack_id = stack.to_string();
+            let pair = pairs.plan(
+                number,
+                &stack_id,
+                config.pullrequest.pairwise_conflict_every,
+            );
+            // Simulated PRs are always created, under the number they were planned with
+            pairs.created(pair, &stack_id);
+            // Like generated PRs, both sides of a pair impact the marker directory
+            if pair.is_some() {
+                targets.insert(config.pullrequest.pairwise_conflict_dir.clone());
+            }
+            // A stacked PR contains its parent's changes
+            if let Some(p) = parent {
+                targets.extend(prs[p].targets.iter().cloned());
+            }
+            prs.push(SimPr {
+                number,
+                arrival,
+                enqueued,
+                targets,
+                parent,
+                pair,
+                merged_at: None,
+                failed: false,
+            });
+            parent = Some(prs.len() - 1);
+        }
+    }
+    Ok(prs)
+}
+
+/// Target names match the `deps=[a,b]` letters generated PRs use.
+fn target_name(index: usize) -> String {
+    if index < 26 {
+        ((b'a' + index as u8) as char).to_string()
+    } else {
+        format!("t{}", index)
+    }
+}
+
+impl<'a> Simulation<'a> {
+    fn new(config: &'a Conf, params: &'a QueueParams) -> Result<Self, String> {
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        let prs = arrivals(config, params, &mut rng)?;
+        let mut enqueue_order: Vec<usize> = (0..prs.len())
+            .filter(|&pr| prs[pr].enqueued.is_some())
+            .collect();
+        enqueue_order.sort_by(|&a, &b| {
+            prs[a]
+                .enqueued
+                .unwrap()
+                .total_cmp(&prs[b].enqueued.unwrap())
+        });
+        Ok(Simulation {
+            config,
+            params,
+            rng,
+            report: SimulationReport {
+                arrived: prs.len(),
+                ..Default::default()
+            },
+            prs,
+            batches: Vec::new(),
+            queue: Vec::new(),
+            enqueue_order,
+            waiting: VecDeque::new(),
+            merged_pairs: BTreeSet::new(),
+            ci_secs: 0.0,
+        })
+    }
+
+    fn run(&mut self) {
+        let horizon = self.params.duration.as_secs_f64() * DRAIN_FACTOR;
+        let mut next_arrival = 0;
+        let mut now = 0.0;
+        let end = loop {
+            while next_arrival < self.enqueue_order.len() && self.enqueued_at(next_arrival) <= now {
+                self.waiting.push_back(self.enqueue_order[next_arrival]);
+                next_arrival += 1;
+            }
+            for batch in self.batches.iter_mut() {
+                if let BatchState::Testing {
+                    started,
+                    ends,
+                    passed,
+                } = batch.state
+                {
+                    if ends <= now {
+                        self.ci_secs += ends - started;
+                        batch.state = BatchState::Done { passed };
+                    }
+                }
+            }
+            self.resolve(now);
+            self.schedule(now);
+
+            let depth = self.waiting.len()
+                + self
+                    .queue
+                    .iter()
+                    .map(|&b| self.batches[b].prs.len())
+                    .sum::<usize>();
+            self.report.max_queue_depth = self.report.max_queue_depth.max(depth);
+
+            let next_end = self
+                .queue
+                .iter()
+                .filter_map(|&b| match self.batches[b].state {
+                    BatchState::Testing { ends, .. } => Some(ends),
+                    _ => None,
+                })
+                .fold(f64::INFINITY, f64::min);
+            let next_start = if next_arrival < self.enqueue_order.len() {
+                self.enqueued_at(next_arrival)
+            } else {
+                f64::INFINITY
+            };
+            let next = next_end.min(next_start);
+            if !next.is_finite() {
+                break now;
+            }
+            if next > horizon {
+                break horizon;
+            }
+            now = next;
+        };
+        self.finish(end.max(self.params.duration.as_secs_f64()));
+    }
+
+    /// Enqueue time of the `nth` PR in enqueue order.
+    fn enqueued_at(&self, nth: usize) -> f64 {
+        self.prs[self.enqueue_order[nth]]
+            .enqueued
+            .unwrap_or_default()
+    }
+
+    /// PRs that land when `pr` merges: with `stack.enqueue = "top"` the queued tip brings its
+    /// whole stack along.
+    fn members(&self, pr: usize) -> Vec<usize> {
+        let mut members = vec![pr];
+        if self.config.stack.enqueue == StackEnqueue::Top {
+            let mut parent = self.prs[pr].parent;
+            while let Some(p) = parent {
+                members.push(p);
+                parent = self.prs[p].parent;
+            }
+        }
+        members
+    }
+
+    /// Merge, retry, bisect or eject finished batches whose ancestors have all left the queue.
+    fn resolve(&mut self, now: f64) {
+        'changed: loop {
+            for pos in 0..self.queue.len() {
+                let id = self.queue[pos];
+                let BatchState::Done { passed } = self.batches[id].state else {
+                    continue;
+                };
+                if self.batches[id]
+                    .tested_on
+                    .iter()
+                    .any(|a| self.queue.contains(a))
+                {
+                    continue;
+                }
+
+                if passed {
+                    self.merge(pos, now);
+                } else if self.params.optimistic && self.passed_on_top_of(id) {
+                    self.report.optimistic_merges += 1;
+                    self.merge(pos, now);
+                } else if self.batches[id].attempts <= self.params.retries {
+                    self.report.retries += 1;
+                    self.batches[id].state = BatchState::Ready;
+                } else {
+                    self.eject(pos, now);
+                }
+                continue 'changed;
+            }
+            break;
+        }
+    }
+
+    fn passed_on_top_of(&self, id: usize) -> bool {
+        self.queue.iter().any(|&d| {
+            self.batches[d].tested_on.contains(&id)
+                && self.batches[d].state == BatchState::Done { passed: true }
+        })
+    }
+
+    fn merge(&mut self, pos: usize, now: f64) {
+        let id = self.queue.remove(pos);
+        for pr in self.batches[id].prs.clone() {
+            for member in self.members(pr) {
+                self.prs[member].merged_at = Some(now);
+                if let Some(side) = self.pair_side(member) {
+                    self.merged_pairs.insert(side);
+                }
+            }
+        }
+    }
+
+    /// Remove a failed batch: restart everything tested on top of it, then either split it in
+    /// two in its place or, for a single PR, fail that PR and the rest of its stack.
+    fn eject(&mut self, pos: usize, now: f64) {
+        let id = self.queue.remove(pos);
+        for &d in &self.queue {
+            if !self.batches[d].tested_on.contains(&id) {
+                continue;
+            }
+            match self.batches[d].state {
+                BatchState::Testing { started, .. } => {
+                    self.ci_secs += now - started;
+                    self.report.restarted_runs += 1;
+                }
+                BatchState::Done { .. } => self.report.restarted_runs += 1,
+                BatchState::Ready => {}
+            }
+            self.batches[d].state = BatchState::Ready;
+            self.batches[d].tested_on.clear();
+        }
+
+        let prs = self.batches[id].prs.clone();
+        if prs.len() > 1 {
+            self.report.bisections += 1;
+            let (first, second) = prs.split_at(prs.len() / 2);
+            let second = self.new_batch(second.to_vec());
+            let first = self.new_batch(first.to_vec());
+            self.queue.insert(pos, second);
+            self.queue.insert(pos, first);
+        } else {
+            for pr in prs {
+                self.fail(pr);
+            }
+        }
+    }
+
+    /// Fail a PR (and the stack below it when only the tip was queued) along with every PR
+    /// stacked on top of it.
+    fn fail(&mut self, pr: usize) {
+        for member in self.members(pr) {
+            self.prs[member].failed = true;
+        }
+        for child in 0..self.prs.len() {
+            if self.prs[child].parent == Some(pr) && !self.prs[child].failed {
+                self.fail(child);
+            }
+        }
+    }
+
+    fn new_batch(&mut self, prs: Vec<usize>) -> usize {
+        self.batches.push(Batch {
+            prs,
+            state: BatchState::Ready,
+            tested_on: Vec::new(),
+            attempts: 0,
+        });
+        self.batches.len() - 1
+    }
+
+    /// Start ready batches in queue order, forming new batches from waiting PRs when there is
+    /// spare capacity.
+    fn schedule(&mut self, now: f64) {
+        // PRs failed with their stack parent are dropped before they are tested again
+        for &id in &self.queue {
+            if self.batches[id].state == BatchState::Ready {
+                let prs = &self.prs;
+                self.batches[id].prs.retain(|&pr| !prs[pr].failed);
+            }
+        }
+        self.queue.retain(|&id| !self.batches[id].prs.is_empty());
+
+        let mut free = self.params.concurrency.saturating_sub(
+            self.queue
+                .iter()
+                .filter(|&&b| matches!(self.batches[b].state, BatchState::Testing { .. }))
+                .count(),
+        );
+        for pos in 0..self.queue.len() {
+            if free == 0 {
+                return;
+            }
+            if self.batches[self.queue[pos]].state == BatchState::Ready {
+                self.start(pos, now);
+                free -= 1;
+            }
+        }
+        self.waiting.retain(|&pr| !self.prs[pr].failed);
+        while free > 0 {
+            let mut prs = Vec::new();
+            while prs.len() < self.params.batch_size {
+                // A stacked PR waits until its parent is merged or queued ahead of it
+                let next = self
+                    .waiting
+                    .iter()
+                    .position(|&pr| match self.prs[pr].parent {
+                        Some(parent) if self.prs[parent].enqueued.is_some() => {
+                            self.prs[parent].merged_at.is_some()
+                                || prs.contains(&parent)
+                                || self
+                                    .queue
+                                    .iter()
+                                    .any(|&b| self.batches[b].prs.contains(&parent))
+                        }
+                        _ => true,
+                    });
+                match next.and_then(|pos| self.waiting.remove(pos)) {
+                    Some(pr) => prs.push(pr),
+                    None => break,
+                }
+            }
+            if prs.is_empty() {
+                return;
+            }
+            let id = self.new_batch(prs);
+            self.queue.push(id);
+            self.start(self.queue.len() - 1, now);
+            free -= 1;
+        }
+    }
+
+    fn start(&mut self, pos: usize, now: f64) {
+        let id = self.queue[pos];
+        let tested_on = self.ancestors(pos);
+
+        let targets: BTreeSet<String> = self.batches[id]
+            .prs
+            .iter()
+            .flat_map(|&pr| self.prs[pr].targets.iter().cloned())
+            .collect();
+        let targets: Vec<String> = targets.into_iter().collect();
+
+        let tree: Vec<usize> = tested_on
+            .iter()
+            .flat_map(|&a| self.batches[a].prs.iter().copied())
+            .chain(self.batches[id].prs.iter().copied())
+            .flat_map(|pr| self.members(pr))
+            .collect();
+        let conflict = self.has_conflict(&tree);
+
+        let timeout = self.config.test_timeout().as_secs_f64();
+        let mut duration = self
+            .config
+            .sample_test_duration(&targets, &mut self.rng)
+            .as_secs_f64();
+        let flaked = self.rng.gen_range(0.0..1.0) < self.config.failure_probability(&targets);
+        let hung = self.rng.gen_range(0.0..1.0) < self.config.test.hang_rate;
+        let timed_out = hung || duration > timeout;
+        if timed_out {
+            duration = timeout;
+        }
+        if (flaked || timed_out) && !conflict {
+            self.report.flaky_failures += 1;
+        }
+
+        self.report.test_runs += 1;
+        let batch = &mut self.batches[id];
+        batch.attempts += 1;
+        batch.tested_on = tested_on;
+        batch.state = BatchState::Testing {
+            started: now,
+            ends: now + duration,
+            passed: !(conflict || flaked || timed_out),
+        };
+    }
+
+    /// Queued batches ahead of `pos` the batch at `pos` is tested on top of: all of them in a
+    /// single queue, otherwise those sharing impacted targets with it or with one another.
+    fn ancestors(&self, pos: usize) -> Vec<usize> {
+        if !self.params.parallel {
+            return self.queue[..pos].to_vec();
+        }
+        let mut targets: BTreeSet<&String> = self.batch_targets(self.queue[pos]);
+        let mut ancestors = Vec::new();
+        for &id in self.queue[..pos].iter().rev() {
+            let ahead = self.batch_targets(id);
+            if !ahead.is_disjoint(&targets) {
+                targets.extend(ahead);
+                ancestors.push(id);
+            }
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    fn batch_targets(&self, id: usize) -> BTreeSet<&String> {
+        self.batches[id]
+            .prs
+            .iter()
+            .flat_map(|&pr| self.prs[pr].targets.iter())
+            .collect()
+    }
+
+    /// Whether a tree made of the merged PRs plus `tree` fails for a logical conflict: a
+    /// logical-conflict PR, or both sides of a pairwise conflict.
+    fn has_conflict(&self, tree: &[usize]) -> bool {
+        let every = self.config.pullrequest.logical_conflict_every;
+        if tree
+            .iter()
+            .any(|&pr| every > 0 && self.prs[pr].number.is_multiple_of(every))
+        {
+            return true;
+        }
+        let mut sides = self.merged_pairs.clone();
+        sides.extend(tree.iter().filter_map(|&pr| self.pair_side(pr)));
+        sides
+            .iter()
+            .any(|&(pair, side)| side == 'a' && sides.contains(&(pair, 'b')))
+    }
+
+    fn pair_side(&self, pr: usize) -> Option<(u32, char)> {
+        self.prs[pr].pair
+    }
+
+    fn finish(&mut self, end: f64) {
+        // Runs still in flight used CI up to the end of the simulation
+        for batch in &self.batches {
+            if let BatchState::Testing { started, ends, .. } = batch.state {
+                self.ci_secs += ends.min(end) - started;
+            }
+        }
+
+        let mut latencies: Vec<f64> = self
+            .prs
+            .iter()
+            .filter_map(|pr| pr.merged_at.map(|m| (m - pr.arrival) / 60.0))
+            .collect();
+        latencies.sort_by(|a, b| a.total_cmp(b));
+
+        let report = &mut self.report;
+        report.merged = latencies.len();
+        report.failed = self.prs.iter().filter(|pr| pr.failed).count();
+        report.queued = report.arrived - report.merged - report.failed;
+        report.simulated_hours = end / 3600.0;
+        if report.simulated_hours > 0.0 {
+            report.throughput_per_hour = report.merged as f64 / report.simulated_hours;
+        }
+        if !latencies.is_empty() {
+            report.latency_mean_mins = latencies.iter().sum::<f64>() / latencies.len() as f64;
+        }
+        report.latency_p50_mins = percentile(&latencies, 0.50);
+        report.latency_p90_mins = percentile(&latencies, 0.90);
+        report.latency_p99_mins = percentile(&latencies, 0.99);
+        report.ci_hours = self.ci_secs / 3600.0;
+    }
+}
+
+/// Nearest-rank percentile of sorted `values` (0 when empty).
+fn percentile(values: &[f64], p: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let rank = (p * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PullRequestConf, TestConf};
+
+    fn config(flake_rate: f32, logical_conflict_every: u32) -> Conf {
+        Conf {
+            pullrequest: PullRequestConf {
+                requests_per_hour: 60,
+                logical_conflict_every,
+                protected_branches: vec!["main".to_string()],
+                max_deps: 1,
+                max_impacted_deps: 1,
+                ..Default::default()
+            },
+            test: TestConf {
+                flake_rate,
+                sleep_for: "10 minutes".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn params(concurrency: usize, batch_size: usize) -> QueueParams {
+        QueueParams {
+            parallel: false,
+            batch_size,
+            concurrency,
+            optimistic: false,
+            retries: 0,
+            duration: Duration::from_secs(4 * 3600),
+            targets: 26,
+            seed: 1,
+        }
+    }
+
+    #[test]
+    fn test_no_flakes_merges_everything() {
+        let report = simulate_queue(&config(0.0, 0), &params(10, 1)).unwrap();
+        assert_eq!(report.arrived, 240);
+        assert_eq!(report.merged, 240);
+        assert_eq!(report.failed, 0);
+        assert_eq!(report.test_runs, 240);
+        assert!(report.latency_p50_mins >= 10.0);
+    }
+
+    #[test]
+    fn test_serial_queue_is_capacity_bound() {
+        // One 10 minute run at a time can merge at most 6 PRs an hour
+        let report = simulate_queue(&config(0.0, 0), &params(1, 1)).unwrap();
+        assert!(report.throughput_per_hour <= 6.0 + 1e-9);
+        assert!(report.queued > 0);
+    }
+
+    #[test]
+    fn test_batching_raises_throughput() {
+        let serial = simulate_queue(&config(0.0, 0), &params(1, 1)).unwrap();
+        let batched = simulate_queue(&config(0.0, 0), &params(1, 8)).unwrap();
+        assert!(batched.merged > serial.merged);
+    }
+
+    #[test]
+    fn test_logical_conflicts_are_ejected() {
+        let report = simulate_queue(&config(0.0, 10), &params(8, 4)).unwrap();
+        assert_eq!(report.failed, 24);
+        assert_eq!(
+            report.merged + report.failed + report.queued,
+            report.arrived
+        );
+        assert!(report.bisections > 0);
+        assert!(report.restarted_runs > 0);
+    }
+
+    #[test]
+    fn test_same_seed_same_report() {
+        let a = simulate_queue(&config(0.2, 0), &params(4, 2)).unwrap();
+        let b = simulate_queue(&config(0.2, 0), &params(4, 2)).unwrap();
+        assert_eq!(a.merged, b.merged);
+        assert_eq!(a.latency_p90_mins, b.latency_p90_mins);
+        assert!(a.flaky_failures > 0);
+    }
+
+    #[test]
+    fn test_retries_and_optimistic_merging_reduce_ejections() {
+        let base = simulate_queue(&config(0.2, 0), &params(4, 1)).unwrap();
+        let mut tolerant = params(4, 1);
+        tolerant.retries = 1;
+        tolerant.optimistic = true;
+        let tolerant = simulate_queue(&config(0.2, 0), &tolerant).unwrap();
+        assert!(tolerant.failed < base.failed);
+    }
+
+    fn stacked_config(enqueue: StackEnqueue) -> Conf {
+        let mut config = config(0.0, 0);
+        config.pullrequest.stacks_distribution = Some("1.0x3".to_string());
+        config.stack.enqueue = enqueue;
+        config.stack.enqueue_interval = "30 seconds".to_string();
+        config
+    }
+
+    #[test]
+    fn test_top_enqueues_only_stack_tips() {
+        let report = simulate_queue(&stacked_config(StackEnqueue::Top), &params(10, 1)).unwrap();
+        assert_eq!(report.merged, report.arrived);
+        assert_eq!(report.test_runs, report.arrived / 3);
+    }
+
+    #[test]
+    fn test_stack_parents_merge_before_children() {
+        for enqueue in [
+            StackEnqueue::Each,
+            StackEnqueue::BottomUp,
+            StackEnqueue::RandomOrder,
+        ] {
+            let config = stacked_config(enqueue);
+            let params = params(10, 2);
+            let mut sim = Simulation::new(&config, &params).unwrap();
+            sim.run();
+
+            assert!(sim.prs.iter().all(|pr| pr.merged_at.is_some()));
+            for pr in &sim.prs {
+                if let Some(parent) = pr.parent {
+                    assert!(sim.prs[parent].merged_at <= pr.merged_at);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_in_flight_runs_count_ci_time() {
+        // A single 10 minute run per PR, far more PRs than fit in the drain window
+        let mut params = params(1, 1);
+        params.duration = Duration::from_secs(600);
+        let report = simulate_queue(&config(0.0, 0), &params).unwrap();
+        assert!(report.queued > 0);
+        assert!(report.ci_hours * 3600.0 > (report.test_runs - 1) as f64 * 600.0);
+    }
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(&[], 0.9), 0.0);
+        let values: Vec<f64> = (1..=10).map(|v| v as f64).collect();
+        assert_eq!(percentile(&values, 0.5), 5.0);
+        assert_eq!(percentile(&values, 0.9), 9.0);
+        assert_eq!(percentile(&values, 1.0), 10.0);
+    }
+}
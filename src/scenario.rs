@@ -0,0 +1,213 @@
+//! Multi-phase load scenarios for `mq run-scenario`.
+//!
+//! A scenario file lists ordered phases. Each phase has a name and a duration, and any other
+//! keys override the matching `mq.toml` sections for that phase only:
+//!
+//! ```toml
+//! ledger = "spike-test.jsonl"
+//!
+//! [[phase]]
+//! name = "warmup"
+//! duration = "10 minutes"
+//! pullrequest.requests_per_hour = 30
+//!
+//! [[phase]]
+//! name = "spike"
+//! duration = "20 minutes"
+//! pullrequest.requests_per_hour = 200
+//! test.flake_rate = 0.3
+//! ```
+
+use crate::config::Conf;
+use confique::Config;
+use parse_duration::parse;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug)]
+pub struct Scenario {
+    /// Ledger file phase boundaries are recorded in; `--ledger` overrides it
+    #[serde(default)]
+    pub ledger: Option<String>,
+
+    #[serde(rename = "phase", default)]
+    pub phases: Vec<Phase>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Phase {
+    pub name: String,
+    pub duration: String,
+
+    /// `Conf` sections overridden for this phase, e.g. `[phase.pullrequest]`
+    #[serde(flatten)]
+    pub overrides: toml::Table,
+}
+
+impl Scenario {
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("could not read scenario '{}': {}", path, e))?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let scenario: Scenario = toml::from_str(content).map_err(|e| e.to_string())?;
+        if scenario.phases.is_empty() {
+            return Err("scenario has no [[phase]] entries".to_string());
+        }
+        for phase in &scenario.phases {
+            phase.duration()?;
+            phase.check_override_keys()?;
+        }
+        Ok(scenario)
+    }
+}
+
+impl Phase {
+    pub fn duration(&self) -> Result<Duration, String> {
+        parse(&self.duration).map_err(|_| format!("phase '{}' has an invalid duration", self.name))
+    }
+
+    /// Reject override keys that are not `Conf` settings, so a typo fails the scenario instead of
+    /// silently running the phase with the default.
+    pub fn check_override_keys(&self) -> Result<(), String> {
+        let known = serde_json::to_value(Conf::default()).map_err(|e| e.to_string())?;
+        match unknown_key(&self.overrides, &known, "") {
+            Some(key) => Err(format!("phase '{}': unknown key '{}'", self.name, key)),
+            None => Ok(()),
+        }
+    }
+
+    /// The phase's overrides as a TOML table. `pullrequest.run_generate_for` is set to the phase
+    /// duration, and setting one of `requests_per_hour`/`requests_per_run` clears the other so a
+    /// phase can switch between distributed and burst mode.
+    pub fn override_table(&self) -> toml::Table {
+        let mut overrides = self.overrides.clone();
+        let pullrequest = overrides
+            .entry("pullrequest")
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        if let toml::Value::Table(pullrequest) = pullrequest {
+            pullrequest.insert(
+                "run_generate_for".to_string(),
+                toml::Value::String(self.duration.clone()),
+            );
+            for (set, cleared) in [
+                ("requests_per_hour", "requests_per_run"),
+                ("requests_per_run", "requests_per_hour"),
+            ] {
+                if pullrequest.contains_key(set) && !pullrequest.contains_key(cleared) {
+                    pullrequest.insert(cleared.to_string(), toml::Value::Integer(0));
+                }
+            }
+        }
+        overrides
+    }
+
+    /// The configuration for this phase: its overrides on top of the usual config sources.
+    pub fn config(&self) -> Result<Conf, String> {
+        let layer: <Conf as Config>::Layer =
+            toml::Value::Table(self.override_table())
+                .try_into()
+                .map_err(|e| format!("phase '{}': {}", self.name, e))?;
+        Conf::builder()
+            .preloaded(layer)
+            .env()
+            .file("mq.toml")
+            .file(".config/mq.toml")
+            .load()
+            .map_err(|e| format!("phase '{}': {}", self.name, e))
+    }
+}
+
+/// First key of `table` (as a dotted path under `prefix`) missing from `known`. Tables are
+/// checked key by key against struct sections; map settings (`None` or empty by default, like
+/// `test.targets`) accept any key.
+fn unknown_key(table: &toml::Table, known: &serde_json::Value, prefix: &str) -> Option<String> {
+    for (key, value) in table {
+        let path = format!("{}{}", prefix, key);
+        let Some(known) = known.get(key) else {
+            return Some(path);
+        };
+        if let (toml::Value::Table(table), serde_json::Value::Object(fields)) = (value, known) {
+            if !fields.is_empty() {
+                if let Some(path) = unknown_key(table, known, &format!("{}.", path)) {
+                    return Some(path);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = r#"
+ledger = "spike.jsonl"
+
+[[phase]]
+name = "warmup"
+duration = "10 minutes"
+pullrequest.requests_per_hour = 30
+
+[[phase]]
+name = "spike"
+duration = "20 minutes"
+test.flake_rate = 0.3
+
+[phase.pullrequest]
+requests_per_hour = 200
+"#;
+
+    #[test]
+    fn test_parse_phases() {
+        let scenario = Scenario::parse(SCENARIO).unwrap();
+        assert_eq!(scenario.ledger.as_deref(), Some("spike.jsonl"));
+        assert_eq!(scenario.phases.len(), 2);
+        assert_eq!(scenario.phases[1].name, "spike");
+        assert_eq!(
+            scenario.phases[1].duration(),
+            Ok(Duration::from_secs(20 * 60))
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Scenario::parse("ledger = \"x\"").is_err());
+        assert!(Scenario::parse("[[phase]]\nname = \"a\"\nduration = \"soon\"").is_err());
+        assert!(Scenario::parse("[[phase]]\nduration = \"1m\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_override_keys() {
+        let typo = "[[phase]]\nname = \"a\"\nduration = \"1m\"\npullrequest.requests_per_hr = 5";
+        assert_eq!(
+            Scenario::parse(typo).unwrap_err(),
+            "phase 'a': unknown key 'pullrequest.requests_per_hr'"
+        );
+        let section =
+            "[[phase]]\nname = \"a\"\nduration = \"1m\"\npullreqest.requests_per_hour = 5";
+        assert!(Scenario::parse(section).is_err());
+
+        // Map settings take any key
+        let targets =
+            "[[phase]]\nname = \"a\"\nduration = \"1m\"\ntest.targets.web.flake_rate = 0.5";
+        assert!(Scenario::parse(targets).is_ok());
+    }
+
+    #[test]
+    fn test_phase_config_applies_overrides() {
+        let scenario = Scenario::parse(SCENARIO).unwrap();
+        let spike = scenario.phases[1].config().unwrap();
+        assert_eq!(spike.pullrequest.requests_per_hour, 200);
+        assert_eq!(spike.pullrequest.requests_per_run, 0);
+        assert_eq!(spike.pullrequest.run_generate_for, "20 minutes");
+        assert!((spike.test.flake_rate - 0.3).abs() < 1e-6);
+
+        let warmup = scenario.phases[0].config().unwrap();
+        assert_eq!(warmup.pullrequest.requests_per_hour, 30);
+        assert_eq!(warmup.pullrequest.run_generate_for, "10 minutes");
+    }
+}
@@ -0,0 +1,199 @@
+//! Background scheduler for work that must happen some time after it is planned, such as
+//! enqueuing a PR minutes after creating it, without blocking the caller.
+//!
+//! Jobs run one at a time, in due order, on a worker thread tied to a `std::thread::scope`.
+//! A job can reschedule itself (e.g. to poll again later or to follow up with another step).
+//! A job that panics is logged and dropped; the worker keeps running the others.
+
+use crate::shutdown;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::Scope;
+use std::time::{Duration, Instant};
+
+/// What to do after a job ran.
+pub enum Next<T> {
+    Done,
+    /// Run `T` again at the given time
+    At(Instant, T),
+    /// Follow up with several jobs, each at its own time
+    All(Vec<(Instant, T)>),
+}
+
+impl<T> Next<T> {
+    /// The same follow-ups with every job converted by `f`.
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Next<U> {
+        match self {
+            Next::Done => Next::Done,
+            Next::At(at, job) => Next::At(at, f(job)),
+            Next::All(jobs) => Next::All(jobs.into_iter().map(|(at, job)| (at, f(job))).collect()),
+        }
+    }
+}
+
+/// The message a panic was raised with, from `catch_unwind`'s payload.
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panic".to_string())
+}
+
+pub struct Scheduler<T> {
+    tx: Sender<(Instant, T)>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl<T: Send> Scheduler<T> {
+    /// Start the worker on `scope`. Once every handle is dropped it finishes the jobs still
+    /// pending, waiting until each is due, and exits; on shutdown or `stop` it drops them instead.
+    pub fn spawn<'scope, 'env, F>(scope: &'scope Scope<'scope, 'env>, mut handler: F) -> Self
+    where
+        F: FnMut(T) -> Next<T> + Send + 'scope,
+        T: 'scope,
+    {
+        let (tx, rx) = mpsc::channel::<(Instant, T)>();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = stopped.clone();
+        scope.spawn(move || {
+            let mut pending: Vec<(Instant, T)> = Vec::new();
+            let mut open = true;
+            loop {
+                if shutdown::requested() || stop.load(Ordering::SeqCst) {
+                    if !pending.is_empty() {
+                        println!("scheduler: dropping {} pending job(s)", pending.len());
+                    }
+                    return;
+                }
+                let next_due = pending.iter().map(|(at, _)| *at).min();
+                if open {
+                    // Wake at least once a second to notice a shutdown
+                    let wait = next_due
+                        .map_or(Duration::from_secs(1), |at| {
+                            at.saturating_duration_since(Instant::now())
+                        })
+                        .min(Duration::from_secs(1));
+                    match rx.recv_timeout(wait) {
+                        Ok(job) => {
+                            pending.push(job);
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => open = false,
+                    }
+                } else {
+                    match next_due {
+                        None => return,
+                        Some(at) => {
+                            shutdown::sleep(
+                                at.saturating_duration_since(Instant::now())
+                                    .min(Duration::from_secs(1)),
+                            );
+                        }
+                    }
+                }
+
+                let now = Instant::now();
+                let Some(index) = pending
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (at, _))| *at <= now)
+                    .min_by_key(|(_, (at, _))| *at)
+                    .map(|(i, _)| i)
+                else {
+                    continue;
+                };
+                let (_, job) = pending.swap_remove(index);
+                match panic::catch_unwind(AssertUnwindSafe(|| handler(job))) {
+                    Ok(Next::Done) => {}
+                    Ok(Next::At(at, job)) => pending.push((at, job)),
+                    Ok(Next::All(jobs)) => pending.extend(jobs),
+                    Err(payload) => eprintln!(
+                        "scheduler: job panicked and was dropped: {}",
+                        panic_message(payload.as_ref())
+                    ),
+                }
+            }
+        });
+        Scheduler { tx, stopped }
+    }
+
+    /// Run `job` at `at` (immediately if it is in the past).
+    pub fn schedule(&self, at: Instant, job: T) {
+        if self.tx.send((at, job)).is_err() {
+            eprintln!("scheduler: worker has stopped - dropping a job");
+        }
+    }
+
+    /// Drop the pending jobs instead of waiting for them, e.g. after a failure.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_runs_jobs_in_due_order_and_drains_on_drop() {
+        let ran = Mutex::new(Vec::new());
+        let start = Instant::now();
+        std::thread::scope(|scope| {
+            let scheduler = Scheduler::spawn(scope, |job: u32| {
+                ran.lock().unwrap().push(job);
+                // Job 1 follows up with job 10 shortly after; job 2 with 20 and 30
+                match job {
+                    1 => Next::At(Instant::now() + Duration::from_millis(50), 10),
+                    2 => Next::All(vec![
+                        (Instant::now() + Duration::from_millis(250), 30),
+                        (Instant::now() + Duration::from_millis(50), 20),
+                    ]),
+                    _ => Next::Done,
+                }
+            });
+            scheduler.schedule(start + Duration::from_millis(200), 3);
+            scheduler.schedule(start + Duration::from_millis(100), 2);
+            scheduler.schedule(start, 1);
+        });
+        assert_eq!(*ran.lock().unwrap(), vec![1, 10, 2, 20, 3, 30]);
+        assert!(start.elapsed() >= Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_panicking_job_does_not_stop_the_worker() {
+        let ran = Mutex::new(Vec::new());
+        std::thread::scope(|scope| {
+            let scheduler = Scheduler::spawn(scope, |job: u32| {
+                if job == 1 {
+                    panic!("job 1 failed");
+                }
+                ran.lock().unwrap().push(job);
+                Next::Done
+            });
+            scheduler.schedule(Instant::now(), 1);
+            scheduler.schedule(Instant::now() + Duration::from_millis(50), 2);
+        });
+        assert_eq!(*ran.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_stop_drops_pending_jobs() {
+        let ran = Mutex::new(Vec::new());
+        let start = Instant::now();
+        std::thread::scope(|scope| {
+            let scheduler = Scheduler::spawn(scope, |job: u32| {
+                ran.lock().unwrap().push(job);
+                Next::Done
+            });
+            scheduler.schedule(start + Duration::from_secs(60), 1);
+            scheduler.stop();
+        });
+        assert!(ran.lock().unwrap().is_empty());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
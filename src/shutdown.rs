@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+/// Install a SIGINT/SIGTERM handler that flags a shutdown instead of killing the process,
+/// so long-running subcommands can finish the step they are on and exit cleanly.
+/// Safe to call more than once.
+pub fn install_handler() {
+    INSTALL.call_once(|| {
+        ctrlc::set_handler(|| {
+            println!("shutdown requested - finishing current step");
+            SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+        })
+        .expect("Failed to install signal handler");
+    });
+}
+
+pub fn requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Sleep for `duration`, waking early if a shutdown is requested.
+/// Returns `false` when the sleep was cut short by a shutdown.
+pub fn sleep(duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if requested() {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(250)));
+    }
+}
@@ -0,0 +1,270 @@
+//! Parameter sweeps over the offline queue simulator (`mq sweep`).
+//!
+//! Every combination of the swept values is simulated with the same seed, so rows differ only
+//! by their parameters, and the combinations are spread over all CPU cores.
+
+use crate::config::Conf;
+use crate::queue_sim::{simulate_queue, QueueParams, SimulationReport};
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// One combination of swept parameters. `requests_per_hour` is None when the config's own
+/// arrival rate is used.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SweepPoint {
+    pub flake_rate: f32,
+    pub batch_size: usize,
+    pub concurrency: usize,
+    pub requests_per_hour: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepRow {
+    #[serde(flatten)]
+    pub point: SweepPoint,
+    #[serde(flatten)]
+    pub report: SimulationReport,
+}
+
+/// Parse a list of values: comma separated ("1,2,4,8"), an inclusive range stepping by 1
+/// ("1..8"), or a range with a step ("0.05..0.2:0.05"). Forms can be mixed ("1,4..6").
+pub fn parse_values(spec: &str) -> Result<Vec<f64>, String> {
+    let mut values = Vec::new();
+    for part in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+        let Some((start, rest)) = part.split_once("..") else {
+            values.push(parse_number(part)?);
+            continue;
+        };
+        let (end, step) = match rest.split_once(':') {
+            Some((end, step)) => (end, parse_number(step)?),
+            None => (rest, 1.0),
+        };
+        let (start, end) = (parse_number(start)?, parse_number(end)?);
+        if step <= 0.0 {
+            return Err(format!("step must be greater than 0 in '{}'", part));
+        }
+        if start > end {
+            return Err(format!("range start exceeds its end in '{}'", part));
+        }
+        // Count steps up front so float steps don't drift past (or short of) the end
+        let steps = ((end - start) / step + 1e-9).floor() as usize;
+        values.extend((0..=steps).map(|i| start + step * i as f64));
+    }
+    if values.is_empty() {
+        return Err(format!("no values in '{}'", spec));
+    }
+    Ok(values)
+}
+
+fn parse_number(s: &str) -> Result<f64, String> {
+    s.trim()
+        .parse::<f64>()
+        .map_err(|_| format!("invalid number '{}'", s.trim()))
+}
+
+/// Like `parse_values`, for parameters that must be positive whole numbers.
+pub fn parse_counts(spec: &str) -> Result<Vec<usize>, String> {
+    parse_values(spec)?
+        .into_iter()
+        .map(|v| {
+            if v >= 1.0 && v.fract() == 0.0 {
+                Ok(v as usize)
+            } else {
+                Err(format!("'{}' must be a whole number of at least 1", v))
+            }
+        })
+        .collect()
+}
+
+/// Every combination of the given values, in row-major order.
+pub fn grid(
+    flake_rates: &[f32],
+    batch_sizes: &[usize],
+    concurrencies: &[usize],
+    requests_per_hour: &[Option<u32>],
+) -> Vec<SweepPoint> {
+    let mut points = Vec::new();
+    for &flake_rate in flake_rates {
+        for &batch_size in batch_sizes {
+            for &concurrency in concurrencies {
+                for &requests_per_hour in requests_per_hour {
+                    points.push(SweepPoint {
+                        flake_rate,
+                        batch_size,
+                        concurrency,
+                        requests_per_hour,
+                    });
+                }
+            }
+        }
+    }
+    points
+}
+
+/// Simulate every point on up to `threads` threads. Rows come back in the order of `points`.
+pub fn run_sweep(
+    config: &Conf,
+    base: &QueueParams,
+    points: &[SweepPoint],
+    threads: usize,
+) -> Result<Vec<SweepRow>, String> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<SimulationReport, String>>>> =
+        Mutex::new(vec![None; points.len()]);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, points.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(point) = points.get(index) else {
+                    break;
+                };
+                let result = simulate_point(config, base, point);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    let results = results.into_inner().unwrap();
+    points
+        .iter()
+        .zip(results)
+        .map(|(point, result)| {
+            let report = result.expect("every sweep point is simulated")?;
+            Ok(SweepRow {
+                point: point.clone(),
+                report,
+            })
+        })
+        .collect()
+}
+
+fn simulate_point(
+    config: &Conf,
+    base: &QueueParams,
+    point: &SweepPoint,
+) -> Result<SimulationReport, String> {
+    let mut config = config.clone();
+    config.test.flake_rate = point.flake_rate;
+    if let Some(rate) = point.requests_per_hour {
+        config.pullrequest.requests_per_hour = rate;
+        config.pullrequest.requests_per_run = 0;
+    }
+    let params = QueueParams {
+        batch_size: point.batch_size,
+        concurrency: point.concurrency,
+        ..base.clone()
+    };
+    simulate_queue(&config, &params)
+}
+
+/// Rows as CSV with a header line.
+pub fn to_csv(rows: &[SweepRow]) -> String {
+    let mut csv = String::from(
+        "flake_rate,batch_size,concurrency,requests_per_hour,arrived,merged,failed,queued,\
+         throughput_per_hour,latency_p50_mins,latency_p90_mins,ci_hours\n",
+    );
+    for row in rows {
+        let (p, r) = (&row.point, &row.report);
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{:.2},{:.1},{:.1},{:.1}\n",
+            p.flake_rate,
+            p.batch_size,
+            p.concurrency,
+            p.requests_per_hour
+                .map(|r| r.to_string())
+                .unwrap_or_default(),
+            r.arrived,
+            r.merged,
+            r.failed,
+            r.queued,
+            r.throughput_per_hour,
+            r.latency_p50_mins,
+            r.latency_p90_mins,
+            r.ci_hours
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PullRequestConf, TestConf};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(parse_values("1,2,4"), Ok(vec![1.0, 2.0, 4.0]));
+        assert_eq!(parse_values("1..3"), Ok(vec![1.0, 2.0, 3.0]));
+        assert_eq!(parse_values("1, 4..6").unwrap().len(), 4);
+        let rates = parse_values("0.05..0.2:0.05").unwrap();
+        assert_eq!(rates.len(), 4);
+        assert!((rates[3] - 0.2).abs() < 1e-9);
+
+        assert!(parse_values("").is_err());
+        assert!(parse_values("3..1").is_err());
+        assert!(parse_values("1..3:0").is_err());
+        assert!(parse_values("a..b").is_err());
+    }
+
+    #[test]
+    fn test_parse_counts() {
+        assert_eq!(parse_counts("1..4:2"), Ok(vec![1, 3]));
+        assert!(parse_counts("0").is_err());
+        assert!(parse_counts("1.5").is_err());
+    }
+
+    #[test]
+    fn test_grid_covers_every_combination() {
+        let points = grid(&[0.1, 0.2], &[1, 2, 4], &[1], &[None, Some(30)]);
+        assert_eq!(points.len(), 12);
+        assert_eq!(points[1].requests_per_hour, Some(30));
+        assert_eq!(points[11].flake_rate, 0.2);
+    }
+
+    #[test]
+    fn test_run_sweep_matches_single_runs() {
+        let config = Conf {
+            pullrequest: PullRequestConf {
+                requests_per_hour: 20,
+                protected_branches: vec!["main".to_string()],
+                max_deps: 1,
+                max_impacted_deps: 1,
+                ..Default::default()
+            },
+            test: TestConf {
+                flake_rate: 0.1,
+                sleep_for: "5 minutes".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let base = QueueParams {
+            parallel: false,
+            batch_size: 1,
+            concurrency: 1,
+            optimistic: false,
+            retries: 0,
+            duration: Duration::from_secs(2 * 3600),
+            targets: 26,
+            seed: 5,
+        };
+        let points = grid(&[0.0, 0.2], &[1, 4], &[2], &[None, Some(40)]);
+        let rows = run_sweep(&config, &base, &points, 3).unwrap();
+
+        assert_eq!(rows.len(), points.len());
+        for (row, point) in rows.iter().zip(&points) {
+            assert_eq!(&row.point, point);
+            let single = simulate_point(&config, &base, point).unwrap();
+            assert_eq!(row.report.merged, single.merged);
+        }
+        assert_eq!(rows[0].report.arrived, 40);
+        assert_eq!(rows[1].report.arrived, 80);
+
+        let csv = to_csv(&rows);
+        assert_eq!(csv.lines().count(), points.len() + 1);
+        assert!(csv.lines().nth(1).unwrap().starts_with("0,1,2,,40,"));
+    }
+}
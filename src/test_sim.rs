@@ -0,0 +1,599 @@
+//! Outcome of a simulated test run (`mq test-sim`), per impacted target.
+//!
+//! `simulate` decides every target's result and duration up front from a seeded RNG, so a
+//! run can be replayed exactly with the same seed. The caller does the sleeping and exiting.
+
+use crate::config::Conf;
+use crate::flake::sticky_draw;
+use crate::github::GitHubContext;
+use rand::Rng;
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Exit code for a run that timed out, matching coreutils `timeout`.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Test case name used when a run has no impacted targets.
+pub const DEFAULT_TARGET: &str = "default";
+
+/// Everything about the environment a simulated run depends on.
+#[derive(Debug, Clone, Default)]
+pub struct SimContext {
+    pub impacted: Vec<String>,
+    pub sha: String,
+    pub attempt: u32,
+    pub seed: u64,
+    pub outage: bool,
+    pub logical_conflict: bool,
+    pub pairwise_conflicts: Vec<u32>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetStatus {
+    Passed,
+    Failed,
+    /// Failed, then passed when retried; does not fail the run
+    Flaky,
+    /// Hung or ran longer than `test.timeout`; fails the run
+    #[serde(rename = "timed_out")]
+    TimedOut,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TargetResult {
+    pub name: String,
+    pub status: TargetStatus,
+    pub duration_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TestRunReport {
+    pub passed: bool,
+    pub timed_out: bool,
+    pub seed: u64,
+    pub sha: String,
+    pub attempt: u32,
+    pub duration_secs: f64,
+    pub timestamp: String,
+    pub targets: Vec<TargetResult>,
+}
+
+impl TestRunReport {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.duration_secs)
+    }
+
+    pub fn failures(&self) -> usize {
+        self.count(TargetStatus::Failed)
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(TargetStatus::TimedOut)
+    }
+
+    /// Process exit code for the run: 0 passed, 1 failed, `TIMEOUT_EXIT_CODE` timed out.
+    pub fn exit_code(&self) -> i32 {
+        if self.timed_out {
+            TIMEOUT_EXIT_CODE
+        } else if self.passed {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn count(&self, status: TargetStatus) -> usize {
+        self.targets.iter().filter(|t| t.status == status).count()
+    }
+
+    /// JUnit XML in the Surefire dialect; flaky targets are reported with `<flakyFailure>`.
+    pub fn to_junit_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites name=\"mq test-sim\" tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            self.targets.len(),
+            self.failures(),
+            self.errors(),
+            self.duration_secs
+        ));
+        xml.push_str(&format!(
+            "  <testsuite name=\"test-sim\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"0\" time=\"{:.3}\" timestamp=\"{}\">\n",
+            self.targets.len(),
+            self.failures(),
+            self.errors(),
+            self.duration_secs,
+            escape_xml(&self.timestamp)
+        ));
+        xml.push_str("    <properties>\n");
+        for (name, value) in [
+            ("seed", self.seed.to_string()),
+            ("sha", self.sha.clone()),
+            ("attempt", self.attempt.to_string()),
+        ] {
+            xml.push_str(&format!(
+                "      <property name=\"{}\" value=\"{}\"/>\n",
+                name,
+                escape_xml(&value)
+            ));
+        }
+        xml.push_str("    </properties>\n");
+
+        for target in &self.targets {
+            let open = format!(
+                "    <testcase classname=\"test-sim\" name=\"{}\" time=\"{:.3}\"",
+                escape_xml(&target.name),
+                target.duration_secs
+            );
+            let reason = escape_xml(target.reason.as_deref().unwrap_or(""));
+            match target.status {
+                TargetStatus::Passed => xml.push_str(&format!("{}/>\n", open)),
+                TargetStatus::Failed => xml.push_str(&format!(
+                    "{}>\n      <failure message=\"{}\" type=\"simulated\"/>\n    </testcase>\n",
+                    open, reason
+                )),
+                TargetStatus::Flaky => xml.push_str(&format!(
+                    "{}>\n      <flakyFailure message=\"{}\" type=\"simulated\"/>\n    </testcase>\n",
+                    open, reason
+                )),
+                TargetStatus::TimedOut => xml.push_str(&format!(
+                    "{}>\n      <error message=\"{}\" type=\"timeout\"/>\n    </testcase>\n",
+                    open, reason
+                )),
+            }
+        }
+
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+
+    /// Write `test-sim.xml` (JUnit) and `test-sim.json` into `dir`, creating it if needed.
+    pub fn write(&self, dir: &str) -> std::io::Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(Path::new(dir).join("test-sim.xml"), self.to_junit_xml())?;
+        let json = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        fs::write(Path::new(dir).join("test-sim.json"), json)
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Decide whether a test-sim run is testing a merge queue branch, and why.
+///
+/// `IS_MERGE=true|false` wins when set. Otherwise a GitHub `merge_group` event, or a branch
+/// (from GITHUB_REF, or the ref/head_ref of the event context) matching one of `patterns`,
+/// marks a merge run. `env` looks up environment variables.
+pub fn detect_merge_run(
+    patterns: &[String],
+    env: impl Fn(&str) -> Option<String>,
+    context: Option<&GitHubContext>,
+) -> (bool, String) {
+    if let Some(is_merge) = env("IS_MERGE").filter(|v| !v.is_empty()) {
+        return (
+            is_merge.eq_ignore_ascii_case("true"),
+            format!("IS_MERGE={}", is_merge),
+        );
+    }
+
+    let event_name =
+        env("GITHUB_EVENT_NAME").or_else(|| context.and_then(|c| c.event_name.clone()));
+    if event_name.as_deref() == Some("merge_group") {
+        return (true, "merge_group event".to_string());
+    }
+
+    match matched_merge_branch(patterns, &env, context) {
+        Some((branch, re)) => (true, format!("branch {} matches {}", branch, re.as_str())),
+        None => (false, "no merge queue context detected".to_string()),
+    }
+}
+
+/// Base branch encoded in a merge queue branch name, e.g. `main` for `trunk-merge/main/123` or
+/// `release/1.x` for `gh-readonly-queue/release/1.x/pr-5-abc`: whatever sits between the
+/// matched pattern and the last path segment.
+pub fn merge_branch_base(
+    patterns: &[String],
+    env: impl Fn(&str) -> Option<String>,
+    context: Option<&GitHubContext>,
+) -> Option<String> {
+    let (branch, re) = matched_merge_branch(patterns, &env, context)?;
+    let matched_end = re.find(&branch)?.end();
+    let (base, _) = branch[matched_end..].rsplit_once('/')?;
+    Some(base.to_string()).filter(|b| !b.is_empty())
+}
+
+/// First branch (from GITHUB_REF, or the ref/head_ref of the event context) matching one of
+/// `patterns`, with the pattern it matched.
+fn matched_merge_branch(
+    patterns: &[String],
+    env: &impl Fn(&str) -> Option<String>,
+    context: Option<&GitHubContext>,
+) -> Option<(String, Regex)> {
+    let mut refs: Vec<String> = Vec::new();
+    refs.extend(env("GITHUB_REF"));
+    if let Some(c) = context {
+        refs.extend(c.git_ref.clone());
+        refs.extend(c.head_ref.clone());
+    }
+
+    let regexes: Vec<Regex> = patterns.iter().filter_map(|p| Regex::new(p).ok()).collect();
+    refs.iter().find_map(|git_ref| {
+        let branch = git_ref.strip_prefix("refs/heads/").unwrap_or(git_ref);
+        regexes
+            .iter()
+            .find(|re| re.is_match(branch))
+            .map(|re| (branch.to_string(), re.clone()))
+    })
+}
+
+/// Decide the outcome of one test run.
+///
+/// Logical conflicts fail every target. Otherwise the global `flake_rate` (raised during an
+/// outage) fails one randomly chosen target, and every target with its own `[test.targets]`
+/// rate may fail independently. With `sticky_flakes` the draws come from the commit SHA, and on
+/// a retry each flaky failure may pass with `retry_pass_rate`.
+pub fn simulate<R: Rng + ?Sized>(config: &Conf, ctx: &SimContext, rng: &mut R) -> TestRunReport {
+    let names: Vec<String> = if ctx.impacted.is_empty() {
+        vec![DEFAULT_TARGET.to_string()]
+    } else {
+        ctx.impacted.clone()
+    };
+
+    let base_duration = config.sleep_distribution().sample(rng);
+
+    let mut targets: Vec<TargetResult> = names
+        .iter()
+        .map(|name| {
+            let duration =
+                config.sample_target_duration(name, ctx.impacted.len(), base_duration, rng);
+            TargetResult {
+                name: name.clone(),
+                status: TargetStatus::Passed,
+                duration_secs: duration.as_secs_f64(),
+                reason: None,
+            }
+        })
+        .collect();
+
+    let conflict_reason = if ctx.logical_conflict {
+        Some("logical conflict".to_string())
+    } else if !ctx.pairwise_conflicts.is_empty() {
+        Some(format!(
+            "pairwise logical conflict (pairs {:?})",
+            ctx.pairwise_conflicts
+        ))
+    } else {
+        None
+    };
+
+    if let Some(reason) = conflict_reason {
+        for target in &mut targets {
+            target.status = TargetStatus::Failed;
+            target.reason = Some(reason.clone());
+        }
+    } else {
+        let mut draw = |salt: &str| -> f32 {
+            if config.test.sticky_flakes {
+                sticky_draw(&format!("{}{}", ctx.sha, salt))
+            } else {
+                rng.gen_range(0.0..1.0)
+            }
+        };
+
+        let mut global_rate = config.test.flake_rate;
+        if ctx.outage {
+            global_rate = 1.0 - (1.0 - global_rate) * (1.0 - config.test.outage_flake_rate);
+        }
+        let global_flake = draw("") < global_rate;
+
+        for target in targets.iter_mut() {
+            let rate = config.target_flake_rate(&target.name);
+            if rate > 0.0 && draw(&format!(":{}", target.name)) < rate {
+                target.status = TargetStatus::Failed;
+                target.reason = Some("target flake".to_string());
+            }
+        }
+
+        if global_flake {
+            let index = rng.gen_range(0..targets.len());
+            targets[index].status = TargetStatus::Failed;
+            targets[index].reason = Some(if ctx.outage {
+                "random flake during outage".to_string()
+            } else {
+                "random flake".to_string()
+            });
+        }
+
+        if ctx.attempt > 1 {
+            for target in targets.iter_mut() {
+                if target.status == TargetStatus::Failed
+                    && rng.gen_range(0.0..1.0) < config.test.retry_pass_rate
+                {
+                    target.status = TargetStatus::Flaky;
+                    target.reason = target
+                        .reason
+                        .take()
+                        .map(|r| format!("{}, passed on retry", r));
+                }
+            }
+        }
+    }
+
+    // A hang runs until the timeout; so does anything whose sampled duration exceeds it.
+    let timeout = config.test_timeout();
+    if rng.gen_range(0.0..1.0) < config.test.hang_rate {
+        let index = rng.gen_range(0..targets.len());
+        targets[index].status = TargetStatus::TimedOut;
+        targets[index].reason = Some("hung".to_string());
+        targets[index].duration_secs = timeout.as_secs_f64();
+    }
+    for target in targets.iter_mut() {
+        if target.duration_secs > timeout.as_secs_f64() {
+            target.status = TargetStatus::TimedOut;
+            target.reason = Some(format!("exceeded timeout of {}s", timeout.as_secs()));
+            target.duration_secs = timeout.as_secs_f64();
+        }
+    }
+
+    let duration_secs = targets.iter().map(|t| t.duration_secs).fold(0.0, f64::max);
+    let timed_out = targets.iter().any(|t| t.status == TargetStatus::TimedOut);
+
+    TestRunReport {
+        passed: !timed_out && targets.iter().all(|t| t.status != TargetStatus::Failed),
+        timed_out,
+        seed: ctx.seed,
+        sha: ctx.sha.clone(),
+        attempt: ctx.attempt,
+        duration_secs,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        targets,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MergeConf, TargetConf, TestConf};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::BTreeMap;
+
+    fn config(flake_rate: f32, targets: &[(&str, f32)]) -> Conf {
+        Conf {
+            test: TestConf {
+                flake_rate,
+                sleep_for: "2 seconds".to_string(),
+                targets: Some(
+                    targets
+                        .iter()
+                        .map(|(k, rate)| {
+                            (
+                                k.to_string(),
+                                TargetConf {
+                                    flake_rate: *rate,
+                                    sleep_for: None,
+                                },
+                            )
+                        })
+                        .collect::<BTreeMap<_, _>>(),
+                ),
+                ..Default::default()
+            },
+            merge: MergeConf::default(),
+            ..Default::default()
+        }
+    }
+
+    fn context(impacted: &[&str]) -> SimContext {
+        SimContext {
+            impacted: impacted.iter().map(|s| s.to_string()).collect(),
+            sha: "abc123".to_string(),
+            attempt: 1,
+            seed: 42,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_same_seed_same_outcome() {
+        let config = config(0.5, &[("a", 0.5)]);
+        let ctx = context(&["a", "b"]);
+        for seed in 0..20 {
+            let a = simulate(&config, &ctx, &mut StdRng::seed_from_u64(seed));
+            let b = simulate(&config, &ctx, &mut StdRng::seed_from_u64(seed));
+            assert_eq!(a.passed, b.passed);
+            assert_eq!(
+                a.targets.iter().map(|t| t.status).collect::<Vec<_>>(),
+                b.targets.iter().map(|t| t.status).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_no_flake_rate_passes() {
+        let config = config(0.0, &[]);
+        let report = simulate(&config, &context(&[]), &mut StdRng::seed_from_u64(1));
+        assert!(report.passed);
+        assert_eq!(report.targets.len(), 1);
+        assert_eq!(report.targets[0].name, DEFAULT_TARGET);
+        assert_eq!(report.duration(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_target_flake_is_attributed() {
+        let config = config(0.0, &[("p", 1.0)]);
+        let report = simulate(
+            &config,
+            &context(&["p", "q"]),
+            &mut StdRng::seed_from_u64(1),
+        );
+        assert!(!report.passed);
+        assert_eq!(report.targets[0].status, TargetStatus::Failed);
+        assert_eq!(report.targets[0].reason.as_deref(), Some("target flake"));
+        assert_eq!(report.targets[1].status, TargetStatus::Passed);
+    }
+
+    #[test]
+    fn test_logical_conflict_fails_every_target() {
+        let config = config(0.0, &[]);
+        let mut ctx = context(&["a", "b"]);
+        ctx.pairwise_conflicts = vec![10];
+        let report = simulate(&config, &ctx, &mut StdRng::seed_from_u64(1));
+        assert!(!report.passed);
+        assert_eq!(report.failures(), 2);
+        assert!(report.targets[0]
+            .reason
+            .as_deref()
+            .unwrap()
+            .contains("pairwise"));
+    }
+
+    #[test]
+    fn test_retry_turns_failure_flaky() {
+        let mut config = config(0.0, &[("p", 1.0)]);
+        config.test.retry_pass_rate = 1.0;
+        let mut ctx = context(&["p"]);
+        ctx.attempt = 2;
+        let report = simulate(&config, &ctx, &mut StdRng::seed_from_u64(1));
+        assert!(report.passed);
+        assert_eq!(report.targets[0].status, TargetStatus::Flaky);
+    }
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    fn default_patterns() -> Vec<String> {
+        vec![
+            "^trunk-merge/".to_string(),
+            "^gh-readonly-queue/".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_detect_merge_run_from_environment() {
+        let patterns = default_patterns();
+        let cases = [
+            (
+                vec![("GITHUB_REF", "refs/heads/trunk-merge/main/123")],
+                true,
+            ),
+            (
+                vec![("GITHUB_REF", "refs/heads/gh-readonly-queue/main/pr-1-abc")],
+                true,
+            ),
+            (vec![("GITHUB_EVENT_NAME", "merge_group")], true),
+            (vec![("GITHUB_REF", "refs/pull/5/merge")], false),
+            (vec![], false),
+            // IS_MERGE overrides detection both ways
+            (
+                vec![("IS_MERGE", "false"), ("GITHUB_EVENT_NAME", "merge_group")],
+                false,
+            ),
+            (vec![("IS_MERGE", "TRUE")], true),
+        ];
+        for (vars, expected) in cases {
+            let (is_merge, reason) = detect_merge_run(&patterns, env(&vars), None);
+            assert_eq!(is_merge, expected, "{:?}: {}", vars, reason);
+        }
+    }
+
+    #[test]
+    fn test_detect_merge_run_from_context() {
+        let context = GitHubContext::from_json(
+            r#"{ "event_name": "push", "ref": "refs/heads/trunk-merge/main/7" }"#,
+        )
+        .unwrap();
+        let (is_merge, _) = detect_merge_run(&default_patterns(), env(&[]), Some(&context));
+        assert!(is_merge);
+
+        let custom = vec!["^queue-".to_string()];
+        let (is_merge, _) = detect_merge_run(&custom, env(&[]), Some(&context));
+        assert!(!is_merge);
+    }
+
+    #[test]
+    fn test_merge_branch_base() {
+        let patterns = default_patterns();
+        let cases = [
+            ("refs/heads/trunk-merge/main/123", Some("main")),
+            ("refs/heads/gh-readonly-queue/main/pr-1-abc", Some("main")),
+            (
+                "refs/heads/gh-readonly-queue/release/1.x/pr-5-abc",
+                Some("release/1.x"),
+            ),
+            ("refs/heads/feature/foo", None),
+        ];
+        for (git_ref, expected) in cases {
+            let base = merge_branch_base(&patterns, env(&[("GITHUB_REF", git_ref)]), None);
+            assert_eq!(base.as_deref(), expected, "{}", git_ref);
+        }
+
+        let context = GitHubContext::from_json(
+            r#"{ "event_name": "push", "ref": "refs/heads/trunk-merge/stable/7" }"#,
+        )
+        .unwrap();
+        let base = merge_branch_base(&patterns, env(&[]), Some(&context));
+        assert_eq!(base.as_deref(), Some("stable"));
+    }
+
+    #[test]
+    fn test_hang_times_out_at_timeout() {
+        let mut config = config(0.0, &[]);
+        config.test.hang_rate = 1.0;
+        config.test.timeout = Some("30 seconds".to_string());
+        let report = simulate(&config, &context(&["a"]), &mut StdRng::seed_from_u64(1));
+        assert!(!report.passed);
+        assert!(report.timed_out);
+        assert_eq!(report.exit_code(), TIMEOUT_EXIT_CODE);
+        assert_eq!(report.duration(), Duration::from_secs(30));
+        assert_eq!(report.targets[0].reason.as_deref(), Some("hung"));
+        assert!(report
+            .to_junit_xml()
+            .contains("<error message=\"hung\" type=\"timeout\"/>"));
+    }
+
+    #[test]
+    fn test_slow_target_exceeds_timeout() {
+        let mut config = config(0.0, &[]);
+        config.test.timeout = Some("1 second".to_string());
+        let report = simulate(&config, &context(&["a"]), &mut StdRng::seed_from_u64(1));
+        assert!(report.timed_out);
+        assert_eq!(report.duration(), Duration::from_secs(1));
+        assert_eq!(report.targets[0].status, TargetStatus::TimedOut);
+
+        config.test.timeout = Some("1 minute".to_string());
+        let report = simulate(&config, &context(&["a"]), &mut StdRng::seed_from_u64(1));
+        assert!(!report.timed_out);
+        assert_eq!(report.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let config = config(0.0, &[("p", 1.0)]);
+        let report = simulate(
+            &config,
+            &context(&["p", "q<&>"]),
+            &mut StdRng::seed_from_u64(1),
+        );
+        let xml = report.to_junit_xml();
+        assert!(xml.contains("tests=\"2\" failures=\"1\""));
+        assert!(xml.contains("<property name=\"seed\" value=\"42\"/>"));
+        assert!(xml.contains("<failure message=\"target flake\" type=\"simulated\"/>"));
+        assert!(xml.contains("name=\"q&lt;&amp;&gt;\""));
+    }
+}
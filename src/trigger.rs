@@ -0,0 +1,898 @@
+//! Enqueue triggers: how a PR is handed to the merge queue under test.
+//!
+//! Each `merge.trigger` value maps to an `EnqueueBackend`. A backend enqueues a single PR, and
+//! knows how its queue takes a stack: a special command on the tip (`/trunk stack`), a label on
+//! the tip for stack-aware queues, or every PR from the bottom up for queues without stack
+//! support.
+//!
+//! `merge.comment`, `merge.labels` and `merge.run` are templates: `{{PR_NUMBER}}`,
+//! `{{BASE_BRANCH}}`, `{{STACK_ID}}`, `{{DEPS}}` and `{{PRIORITY}}` are replaced per PR. Without a
+//! priority, words using `{{PRIORITY}}` are left out so the queue applies its default.
+
+use crate::cli::Cli;
+use crate::config::{Conf, EnqueueTrigger, GitHubEnqueueMethod};
+use crate::github::GitHub;
+use crate::process::{git, run_with_timeout};
+use crate::shutdown;
+use crate::trunk::{cancel_pull_request, get_targets, submit_pull_request};
+use regex::Regex;
+
+pub const TRUNK_STACK_COMMENT: &str = "/trunk stack";
+pub const MERGIFY_QUEUE_COMMENT: &str = "@mergifyio queue";
+pub const BORS_APPROVE_COMMENT: &str = "bors r+";
+pub const TRUNK_CANCEL_COMMENT: &str = "/trunk cancel";
+pub const MERGIFY_DEQUEUE_COMMENT: &str = "@mergifyio dequeue";
+pub const BORS_CANCEL_COMMENT: &str = "bors r-";
+
+/// What the caller knows about a PR for template placeholders. Values left as None are looked
+/// up from the PR on GitHub when a template uses them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnqueueVars {
+    pub base_branch: Option<String>,
+    pub stack_id: Option<String>,
+    pub deps: Option<Vec<String>>,
+}
+
+pub struct EnqueueContext<'a> {
+    pub config: &'a Conf,
+    pub cli: &'a Cli,
+    pub gh_token: &'a str,
+    /// Queue priority for the PR; None uses the queue's default
+    pub priority: Option<&'a str>,
+    pub vars: EnqueueVars,
+}
+
+impl EnqueueContext<'_> {
+    /// Render `templates` for `pr`, looking up placeholders `vars` does not have.
+    pub fn render(&self, pr: &str, templates: &[&str]) -> Result<Vec<String>, String> {
+        let vars = self.resolve(pr, |placeholder| {
+            templates.iter().any(|t| t.contains(placeholder))
+        })?;
+        Ok(templates
+            .iter()
+            .map(|t| render_text(t, pr, self.priority, &vars))
+            .collect())
+    }
+
+    /// `vars` with the values for the placeholders `uses` asks for looked up on GitHub. Fails
+    /// when the base branch cannot be read rather than guessing one.
+    pub fn resolve(&self, pr: &str, uses: impl Fn(&str) -> bool) -> Result<EnqueueVars, String> {
+        let mut vars = self.vars.clone();
+        if vars.base_branch.is_none() && uses("{{BASE_BRANCH}}") {
+            vars.base_branch = Some(
+                GitHub::try_get_pr_base_branch(pr, self.gh_token)
+                    .map_err(|e| format!("Failed to get the base branch of PR {}: {}", pr, e))?,
+            );
+        }
+        if vars.deps.is_none() && uses("{{DEPS}}") {
+            vars.deps = Some(
+                GitHub::get_pr_body(pr, self.gh_token)
+                    .map(|body| get_targets(&body))
+                    .unwrap_or_default(),
+            );
+        }
+        if vars.stack_id.is_none() && uses("{{STACK_ID}}") {
+            vars.stack_id = GitHub::get_pr_head_branch(pr, self.gh_token)
+                .ok()
+                .and_then(|branch| stack_id_from_branch(&branch));
+        }
+        Ok(vars)
+    }
+
+    /// This context for another PR of the same stack.
+    fn for_stack_pr(&self) -> Self {
+        EnqueueContext {
+            vars: EnqueueVars {
+                stack_id: self.vars.stack_id.clone(),
+                ..Default::default()
+            },
+            ..*self
+        }
+    }
+}
+
+/// Leave out the words that use `{{PRIORITY}}` (and the flag before a bare `{{PRIORITY}}`, as
+/// in `-p {{PRIORITY}}`), for a PR without a priority. Entries with whitespace, like a shell
+/// script, are kept whole for `render_template` to handle word by word.
+pub fn without_priority<'a>(words: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut kept: Vec<&str> = Vec::new();
+    for word in words {
+        if !word.contains("{{PRIORITY}}") || word.contains(char::is_whitespace) {
+            kept.push(word);
+            continue;
+        }
+        if word.trim() == "{{PRIORITY}}" && kept.last().is_some_and(|w| w.starts_with('-')) {
+            kept.pop();
+        }
+    }
+    kept
+}
+
+/// Render comment or label text: like `render_template`, but without a priority the words using
+/// `{{PRIORITY}}` are dropped first.
+pub fn render_text(template: &str, pr: &str, priority: Option<&str>, vars: &EnqueueVars) -> String {
+    match priority {
+        Some(_) => render_template(template, pr, priority, vars),
+        None => render_template(
+            &without_priority(template.split(' ')).join(" "),
+            pr,
+            priority,
+            vars,
+        ),
+    }
+}
+
+/// Replace the placeholders in `template`, a single argument or text as a whole. Unknown values
+/// become empty; `{{DEPS}}` is comma separated.
+pub fn render_template(
+    template: &str,
+    pr: &str,
+    priority: Option<&str>,
+    vars: &EnqueueVars,
+) -> String {
+    let deps = vars
+        .deps
+        .iter()
+        .flatten()
+        .filter(|d| !d.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(",");
+    template
+        .replace("{{PR_NUMBER}}", pr)
+        .replace("{{BASE_BRANCH}}", vars.base_branch.as_deref().unwrap_or(""))
+        .replace("{{STACK_ID}}", vars.stack_id.as_deref().unwrap_or(""))
+        .replace("{{DEPS}}", &deps)
+        .replace("{{PRIORITY}}", priority.unwrap_or(""))
+}
+
+/// The stack id of a generated PR's head branch, `stack-change/{stack_id}-{position}`.
+pub fn stack_id_from_branch(branch: &str) -> Option<String> {
+    let rest = branch.strip_prefix("stack-change/")?;
+    let (stack_id, position) = rest.rsplit_once('-')?;
+    position.parse::<usize>().ok()?;
+    Some(stack_id.to_string())
+}
+
+pub trait EnqueueBackend {
+    /// The `merge.trigger` value, for messages
+    fn name(&self) -> &'static str;
+
+    /// Whether the queue honours `EnqueueContext::priority`, natively or through a
+    /// `{{PRIORITY}}` placeholder
+    fn supports_priority(&self, _config: &Conf) -> bool {
+        false
+    }
+
+    /// Put a single PR into the queue.
+    fn enqueue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String>;
+
+    /// Put a stack into the queue given the PR at its tip, returning the PRs actually enqueued
+    /// (the ones to dequeue to take the stack back out). By default the queue is assumed to
+    /// understand stacks and the tip is enqueued like any other PR.
+    fn enqueue_stack(&self, ctx: &EnqueueContext, tip: &str) -> Result<Vec<String>, String> {
+        self.enqueue(ctx, tip)?;
+        Ok(vec![tip.to_string()])
+    }
+
+    /// Take a PR back out of the queue.
+    fn dequeue(&self, _ctx: &EnqueueContext, _pr: &str) -> Result<(), String> {
+        Err(format!(
+            "merge trigger '{}' does not support dequeuing PRs",
+            self.name()
+        ))
+    }
+}
+
+/// The backend for a `merge.trigger` value.
+pub fn backend(trigger: &EnqueueTrigger) -> Box<dyn EnqueueBackend> {
+    match trigger {
+        EnqueueTrigger::Comment => Box::new(CommentBackend::trunk()),
+        EnqueueTrigger::Mergify => Box::new(CommentBackend::mergify()),
+        EnqueueTrigger::Bors => Box::new(CommentBackend::bors()),
+        EnqueueTrigger::Label => Box::new(LabelBackend { trigger: "label" }),
+        EnqueueTrigger::Graphite => Box::new(LabelBackend {
+            trigger: "graphite",
+        }),
+        EnqueueTrigger::Run => Box::new(RunBackend),
+        EnqueueTrigger::Api => Box::new(TrunkApiBackend),
+        EnqueueTrigger::Github => Box::new(GitHubBackend),
+    }
+}
+
+/// Enqueue `pr` with the configured trigger. `as_stack`: `pr` is the tip of a multi-PR stack.
+/// Returns the PRs that went into the queue: `pr`, or for a stack whichever members the queue
+/// takes it through.
+pub fn enqueue(
+    pr: &str,
+    config: &Conf,
+    cli: &Cli,
+    gh_token: &str,
+    as_stack: bool,
+    priority: Option<&str>,
+    vars: EnqueueVars,
+) -> Result<Vec<String>, String> {
+    let backend = backend(&config.merge.trigger);
+    let supports_priority = backend.supports_priority(config);
+    if priority.is_some() && !supports_priority {
+        println!(
+            "merge trigger '{}' has no priorities - enqueuing with the default",
+            backend.name()
+        );
+    }
+    let ctx = EnqueueContext {
+        config,
+        cli,
+        gh_token,
+        priority: priority.filter(|_| supports_priority),
+        vars,
+    };
+    if as_stack {
+        backend.enqueue_stack(&ctx, pr)
+    } else {
+        backend.enqueue(&ctx, pr)?;
+        Ok(vec![pr.to_string()])
+    }
+}
+
+/// Take `pr` back out of the queue with the configured trigger.
+pub fn dequeue(pr: &str, config: &Conf, cli: &Cli, gh_token: &str) -> Result<(), String> {
+    let ctx = EnqueueContext {
+        config,
+        cli,
+        gh_token,
+        priority: None,
+        vars: EnqueueVars::default(),
+    };
+    backend(&config.merge.trigger).dequeue(&ctx, pr)
+}
+
+/// Repository owner and name parsed from the `origin` remote URL.
+pub fn get_repo_info() -> Result<(String, String), String> {
+    let remote_url = git(&["config", "--get", "remote.origin.url"]);
+    let re = Regex::new(r"[:/]([^/]+)/([^/]+?)(?:\.git)?$").unwrap();
+
+    if let Some(caps) = re.captures(&remote_url) {
+        let owner = caps.get(1).map_or("", |m| m.as_str()).to_string();
+        let name = caps.get(2).map_or("", |m| m.as_str()).to_string();
+        Ok((owner, name))
+    } else {
+        Err("Could not parse repository owner and name from remote URL".to_string())
+    }
+}
+
+/// The open PRs of the stack `tip` belongs to, bottom first, for queues without stack support:
+/// each is enqueued on its own, and the ones above the bottom are retargeted as it merges.
+fn stack_members(ctx: &EnqueueContext, tip: &str) -> Result<Vec<String>, String> {
+    let members = GitHub::find_stack(
+        tip,
+        &ctx.config.pullrequest.protected_branches,
+        ctx.gh_token,
+    )?;
+    println!(
+        "PR {} is stacked - enqueuing its PRs bottom-up: {}",
+        tip,
+        members.join(", ")
+    );
+    Ok(members)
+}
+
+/// How a comment-driven queue takes a stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommentStack {
+    /// Post this comment on the tip instead of the usual one
+    Command(&'static str),
+    /// The queue has no stacks: comment on every PR of the stack, bottom first
+    Bottom,
+}
+
+/// Queues driven by PR comments: Trunk (`merge.comment`), Mergify and bors.
+pub struct CommentBackend {
+    pub trigger: &'static str,
+    /// Fixed enqueue comment; None uses `merge.comment`
+    pub comment: Option<&'static str>,
+    pub stack: CommentStack,
+    /// Comment that takes a PR back out of the queue
+    pub cancel: &'static str,
+    /// Whether the enqueue comment takes a `--priority=<priority>` flag, added when a priority
+    /// is set and the comment has no `{{PRIORITY}}` placeholder
+    pub priority_flag: bool,
+}
+
+impl CommentBackend {
+    pub fn trunk() -> Self {
+        CommentBackend {
+            trigger: "comment",
+            comment: None,
+            stack: CommentStack::Command(TRUNK_STACK_COMMENT),
+            cancel: TRUNK_CANCEL_COMMENT,
+            priority_flag: true,
+        }
+    }
+
+    pub fn mergify() -> Self {
+        CommentBackend {
+            trigger: "mergify",
+            comment: Some(MERGIFY_QUEUE_COMMENT),
+            stack: CommentStack::Bottom,
+            cancel: MERGIFY_DEQUEUE_COMMENT,
+            priority_flag: false,
+        }
+    }
+
+    pub fn bors() -> Self {
+        CommentBackend {
+            trigger: "bors",
+            comment: Some(BORS_APPROVE_COMMENT),
+            stack: CommentStack::Bottom,
+            cancel: BORS_CANCEL_COMMENT,
+            priority_flag: false,
+        }
+    }
+
+    /// The comment template that enqueues a single PR.
+    pub fn comment(&self, config: &Conf, priority: Option<&str>) -> String {
+        let comment = self.comment.unwrap_or(config.merge.comment.as_str());
+        if priority.is_some()
+            && self.priority_flag
+            && !comment.is_empty()
+            && !comment.contains("{{PRIORITY}}")
+        {
+            format!("{} --priority={{{{PRIORITY}}}}", comment)
+        } else {
+            comment.to_string()
+        }
+    }
+
+    fn post(&self, ctx: &EnqueueContext, pr: &str, body: &str) -> Result<(), String> {
+        if body.is_empty() {
+            return Err(format!(
+                "Cannot enqueue PR {} because merge 'trigger' is set to {} but no comment was provided",
+                pr, self.trigger
+            ));
+        }
+        GitHub::try_comment(pr, body, ctx.gh_token)
+            .map_err(|e| format!("Failed to comment on PR {}: {}", pr, e))?;
+        Ok(())
+    }
+}
+
+impl EnqueueBackend for CommentBackend {
+    fn name(&self) -> &'static str {
+        self.trigger
+    }
+
+    fn supports_priority(&self, config: &Conf) -> bool {
+        self.priority_flag || self.comment(config, None).contains("{{PRIORITY}}")
+    }
+
+    fn enqueue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        let comment = self.comment(ctx.config, ctx.priority);
+        let body = ctx.render(pr, &[&comment])?.remove(0);
+        self.post(ctx, pr, &body)
+    }
+
+    fn enqueue_stack(&self, ctx: &EnqueueContext, tip: &str) -> Result<Vec<String>, String> {
+        match self.stack {
+            CommentStack::Command(body) => {
+                // The stack command has no priority flag
+                if let Some(priority) = ctx.priority {
+                    println!(
+                        "'{}' takes no priority - enqueuing stack {} without priority {}",
+                        body, tip, priority
+                    );
+                }
+                self.post(ctx, tip, body)?;
+                Ok(vec![tip.to_string()])
+            }
+            CommentStack::Bottom => {
+                let members = stack_members(ctx, tip)?;
+                for pr in &members {
+                    if pr == tip {
+                        self.enqueue(ctx, pr)?;
+                    } else {
+                        self.enqueue(&ctx.for_stack_pr(), pr)?;
+                    }
+                }
+                Ok(members)
+            }
+        }
+    }
+
+    fn dequeue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        self.post(ctx, pr, self.cancel)
+    }
+}
+
+/// Queues driven by PR labels: a generic label trigger, and Graphite, whose merge queue is
+/// stack-aware and merges everything below a labelled PR.
+pub struct LabelBackend {
+    pub trigger: &'static str,
+}
+
+/// The comma separated `merge.labels` templates, trimmed, without empty entries.
+pub fn split_labels(labels: &str) -> Vec<&str> {
+    labels
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+impl EnqueueBackend for LabelBackend {
+    fn name(&self) -> &'static str {
+        self.trigger
+    }
+
+    fn supports_priority(&self, config: &Conf) -> bool {
+        config.merge.labels.contains("{{PRIORITY}}")
+    }
+
+    fn enqueue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        let templates = split_labels(&ctx.config.merge.labels);
+        if templates.is_empty() {
+            return Err(format!(
+                "Cannot enqueue PR {} because merge 'trigger' is set to {} but no labels were provided",
+                pr, self.trigger
+            ));
+        }
+        let labels: Vec<String> = ctx
+            .render(pr, &templates)?
+            .into_iter()
+            .filter(|l| !l.is_empty())
+            .collect();
+        GitHub::add_labels(pr, &labels, ctx.gh_token)
+            .map_err(|e| format!("Failed to label PR {}: {}", pr, e))?;
+        Ok(())
+    }
+}
+
+/// Environment for the run command: `MQ_PR_NUMBER`, `MQ_BASE_BRANCH`, `MQ_STACK_ID`, `MQ_DEPS`
+/// and `MQ_PRIORITY`, empty when unknown (values are only looked up for a command that mentions
+/// them).
+pub fn run_env(pr: &str, priority: Option<&str>, vars: &EnqueueVars) -> Vec<(String, String)> {
+    [
+        ("MQ_PR_NUMBER", "{{PR_NUMBER}}"),
+        ("MQ_BASE_BRANCH", "{{BASE_BRANCH}}"),
+        ("MQ_STACK_ID", "{{STACK_ID}}"),
+        ("MQ_DEPS", "{{DEPS}}"),
+        ("MQ_PRIORITY", "{{PRIORITY}}"),
+    ]
+    .into_iter()
+    .map(|(key, placeholder)| {
+        (
+            key.to_string(),
+            render_template(placeholder, pr, priority, vars),
+        )
+    })
+    .collect()
+}
+
+/// Runs `merge.run`. Arguments are rendered one by one, so a value with spaces stays a single
+/// argument; in shell mode prefer the `$MQ_*` variables to placeholders, which are not quoted.
+pub struct RunBackend;
+
+impl EnqueueBackend for RunBackend {
+    fn name(&self) -> &'static str {
+        "run"
+    }
+
+    fn supports_priority(&self, config: &Conf) -> bool {
+        let merge = &config.merge;
+        merge
+            .run
+            .templates(merge.run_shell)
+            .iter()
+            .any(|t| t.contains("{{PRIORITY}}") || t.contains("MQ_PRIORITY"))
+    }
+
+    fn enqueue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        let merge = &ctx.config.merge;
+        if merge.run.is_empty() {
+            return Err(format!(
+                "Cannot enqueue PR {} because merge 'trigger' is set to run but no run command was provided",
+                pr
+            ));
+        }
+        let mut templates = merge.run.templates(merge.run_shell);
+        // Only look up the values the command mentions, as a placeholder or as its variable
+        let vars = ctx.resolve(pr, |placeholder| {
+            let variable = format!("MQ_{}", placeholder.trim_matches(|c| c == '{' || c == '}'));
+            templates
+                .iter()
+                .any(|t| t.contains(placeholder) || t.contains(&variable))
+        })?;
+        if ctx.priority.is_none() {
+            templates = without_priority(templates);
+        }
+        let argv: Vec<String> = templates
+            .iter()
+            .map(|t| render_template(t, pr, ctx.priority, &vars))
+            .collect();
+        let env = run_env(pr, ctx.priority, &vars);
+        let timeout = ctx.config.run_timeout_duration();
+
+        let mut delay = ctx.config.run_retry_delay_duration();
+        let mut attempt = 0;
+        loop {
+            println!("run command: {:?}", argv);
+            match run_with_timeout(&argv, &env, timeout) {
+                Ok(output) => {
+                    println!("merge run results: {}", output);
+                    return Ok(());
+                }
+                Err(e) if attempt < merge.run_retries => {
+                    attempt += 1;
+                    eprintln!(
+                        "run command for PR {} failed: {} - retry {}/{} in {}s",
+                        pr,
+                        e,
+                        attempt,
+                        merge.run_retries,
+                        delay.as_secs()
+                    );
+                    if !shutdown::sleep(delay) {
+                        return Err(format!("run command for PR {} failed: {}", pr, e));
+                    }
+                    delay *= 2;
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "run command for PR {} failed after {} attempt(s): {}",
+                        pr,
+                        attempt + 1,
+                        e
+                    ))
+                }
+            }
+        }
+    }
+}
+
+pub struct TrunkApiBackend;
+
+/// Repository, PR number and base branch of `pr`, as the Trunk API identifies a queued PR.
+fn trunk_pr(ctx: &EnqueueContext, pr: &str) -> Result<(String, String, u32, String), String> {
+    let (owner, name) =
+        get_repo_info().map_err(|e| format!("Failed to get repository information: {}", e))?;
+    let pr_number: u32 = pr
+        .parse()
+        .map_err(|_| format!("Invalid PR number: {}", pr))?;
+    let target_branch = ctx
+        .resolve(pr, |placeholder| placeholder == "{{BASE_BRANCH}}")?
+        .base_branch
+        .unwrap_or_default();
+    Ok((owner, name, pr_number, target_branch))
+}
+
+impl EnqueueBackend for TrunkApiBackend {
+    fn name(&self) -> &'static str {
+        "api"
+    }
+
+    fn supports_priority(&self, _config: &Conf) -> bool {
+        true
+    }
+
+    fn enqueue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        // TRUNK_TOKEN will be checked at runtime in submit_pull_request
+        let (owner, name, pr_number, target_branch) = trunk_pr(ctx, pr)?;
+        println!(
+            "Enqueuing PR {} targeting branch: {} (priority: {})",
+            pr,
+            target_branch,
+            ctx.priority.unwrap_or("default")
+        );
+        submit_pull_request(
+            &owner,
+            &name,
+            pr_number,
+            &target_branch,
+            ctx.priority,
+            &ctx.config.trunk.api,
+            ctx.cli,
+        )
+        .map_err(|e| format!("Failed to submit PR {} to Trunk merge queue: {}", pr, e))?;
+        println!(
+            "Successfully submitted PR {} to Trunk merge queue (target: {})",
+            pr, target_branch
+        );
+        Ok(())
+    }
+
+    fn dequeue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        let (owner, name, pr_number, target_branch) = trunk_pr(ctx, pr)?;
+        cancel_pull_request(
+            &owner,
+            &name,
+            pr_number,
+            &target_branch,
+            &ctx.config.trunk.api,
+            ctx.cli,
+        )
+        .map_err(|e| format!("Failed to cancel PR {} in Trunk merge queue: {}", pr, e))?;
+        println!(
+            "Cancelled PR {} in Trunk merge queue (target: {})",
+            pr, target_branch
+        );
+        Ok(())
+    }
+}
+
+/// GitHub's native merge queue (see `merge.github_method`).
+pub struct GitHubBackend;
+
+impl EnqueueBackend for GitHubBackend {
+    fn name(&self) -> &'static str {
+        "github"
+    }
+
+    fn enqueue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        match ctx.config.merge.github_method {
+            GitHubEnqueueMethod::Enqueue => {
+                let entry = GitHub::enqueue_pr(pr, ctx.gh_token).map_err(|e| {
+                    format!("Failed to add PR {} to the GitHub merge queue: {}", pr, e)
+                })?;
+                println!(
+                    "Added PR {} to the GitHub merge queue (state: {}, position: {})",
+                    pr,
+                    entry.state,
+                    entry
+                        .position
+                        .map_or("unknown".to_string(), |p| p.to_string())
+                );
+            }
+            GitHubEnqueueMethod::AutoMerge => {
+                GitHub::enable_auto_merge(pr, ctx.gh_token)
+                    .map_err(|e| format!("Failed to enable auto-merge on PR {}: {}", pr, e))?;
+                println!("Enabled auto-merge on PR {}", pr);
+            }
+        }
+        Ok(())
+    }
+
+    /// GitHub's queue has no stacks: the bottom PR is enqueued with `merge.github_method`, and
+    /// auto-merge is enabled on the PRs above it, so each joins the queue once GitHub retargets
+    /// it to the protected branch.
+    fn enqueue_stack(&self, ctx: &EnqueueContext, tip: &str) -> Result<Vec<String>, String> {
+        let members = stack_members(ctx, tip)?;
+        let Some((bottom, above)) = members.split_first() else {
+            return Ok(members);
+        };
+        self.enqueue(ctx, bottom)?;
+        for pr in above {
+            GitHub::enable_auto_merge(pr, ctx.gh_token)
+                .map_err(|e| format!("Failed to enable auto-merge on PR {}: {}", pr, e))?;
+            println!("Enabled auto-merge on stacked PR {}", pr);
+        }
+        Ok(members)
+    }
+
+    fn dequeue(&self, ctx: &EnqueueContext, pr: &str) -> Result<(), String> {
+        GitHub::dequeue_pr(pr, ctx.gh_token).map_err(|e| {
+            format!(
+                "Failed to remove PR {} from the GitHub merge queue: {}",
+                pr, e
+            )
+        })?;
+        println!("Removed PR {} from the GitHub merge queue", pr);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MergeConf;
+
+    fn config_with_comment(comment: &str) -> Conf {
+        Conf {
+            merge: MergeConf {
+                comment: comment.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_comment_backends() {
+        let config = config_with_comment("/trunk merge");
+
+        let trunk = CommentBackend::trunk();
+        assert_eq!(trunk.comment(&config, None), "/trunk merge");
+        assert_eq!(
+            trunk.comment(&config, Some("urgent")),
+            "/trunk merge --priority={{PRIORITY}}"
+        );
+        assert_eq!(trunk.stack, CommentStack::Command(TRUNK_STACK_COMMENT));
+
+        // A template that places the priority itself gets no extra flag
+        let templated = config_with_comment("/trunk merge -p {{PRIORITY}}");
+        assert_eq!(
+            trunk.comment(&templated, Some("high")),
+            "/trunk merge -p {{PRIORITY}}"
+        );
+
+        // Mergify and bors use their own commands regardless of merge.comment
+        let mergify = CommentBackend::mergify();
+        assert_eq!(mergify.comment(&config, Some("high")), "@mergifyio queue");
+        assert!(!mergify.supports_priority(&config));
+        assert_eq!(mergify.cancel, "@mergifyio dequeue");
+        assert_eq!(CommentBackend::bors().comment(&config, None), "bors r+");
+        assert_eq!(CommentBackend::bors().stack, CommentStack::Bottom);
+    }
+
+    #[test]
+    fn test_missing_trigger_config_is_an_error() {
+        use clap::Parser;
+
+        let cli = Cli::parse_from(["mq"]);
+        for trigger in [
+            EnqueueTrigger::Comment,
+            EnqueueTrigger::Label,
+            EnqueueTrigger::Run,
+        ] {
+            let config = Conf {
+                merge: MergeConf {
+                    trigger,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let err =
+                enqueue("42", &config, &cli, "", false, None, EnqueueVars::default()).unwrap_err();
+            assert!(err.starts_with("Cannot enqueue PR 42"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_render_template() {
+        let vars = EnqueueVars {
+            base_branch: Some("main".to_string()),
+            stack_id: Some("k3x9a".to_string()),
+            deps: Some(vec!["a".to_string(), "b".to_string()]),
+        };
+        assert_eq!(
+            render_template(
+                "/trunk merge --priority={{PRIORITY}} # {{PR_NUMBER}} {{BASE_BRANCH}} {{STACK_ID}} [{{DEPS}}]",
+                "42",
+                Some("high"),
+                &vars
+            ),
+            "/trunk merge --priority=high # 42 main k3x9a [a,b]"
+        );
+        assert_eq!(
+            render_template(
+                "queue-{{PRIORITY}}{{DEPS}}",
+                "42",
+                Some(""),
+                &EnqueueVars::default()
+            ),
+            "queue-"
+        );
+        // One argument stays one argument, spaces and all
+        assert_eq!(
+            render_template(
+                "--title=fix {{STACK_ID}}  for {{PR_NUMBER}}",
+                "42",
+                None,
+                &vars
+            ),
+            "--title=fix k3x9a  for 42"
+        );
+    }
+
+    #[test]
+    fn test_render_text() {
+        let vars = EnqueueVars::default();
+        assert_eq!(render_text("queue-{{PRIORITY}}", "42", None, &vars), "");
+        // Without a priority the queue's default applies: no empty flag
+        assert_eq!(
+            render_text(
+                "/trunk merge --priority={{PRIORITY}} #{{PR_NUMBER}}",
+                "42",
+                None,
+                &vars
+            ),
+            "/trunk merge #42"
+        );
+        assert_eq!(
+            render_text("/trunk merge -p {{PRIORITY}}", "42", None, &vars),
+            "/trunk merge"
+        );
+        assert_eq!(
+            render_text("/trunk merge -p {{PRIORITY}}", "42", Some("high"), &vars),
+            "/trunk merge -p high"
+        );
+    }
+
+    #[test]
+    fn test_without_priority() {
+        assert_eq!(
+            without_priority([
+                "queue",
+                "-p",
+                "{{PRIORITY}}",
+                "--priority={{PRIORITY}}",
+                "x"
+            ]),
+            vec!["queue", "x"]
+        );
+        assert_eq!(
+            without_priority(["sh", "-c", "run -p {{PRIORITY}}"]),
+            vec!["sh", "-c", "run -p {{PRIORITY}}"]
+        );
+    }
+
+    #[test]
+    fn test_stack_id_from_branch() {
+        assert_eq!(
+            stack_id_from_branch("stack-change/k3x9a-2").as_deref(),
+            Some("k3x9a")
+        );
+        assert_eq!(stack_id_from_branch("stack-change/k3x9a"), None);
+        assert_eq!(stack_id_from_branch("feature/k3x9a-2"), None);
+    }
+
+    #[test]
+    fn test_run_backend_runs_argv_with_env_and_retries() {
+        use crate::config::RunCommand;
+        use clap::Parser;
+
+        let dir = std::env::temp_dir().join(format!("mq_run_trigger_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out.txt");
+        let mut config = Conf {
+            merge: MergeConf {
+                trigger: EnqueueTrigger::Run,
+                run: RunCommand::Argv(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    "echo \"$MQ_PR_NUMBER $MQ_BASE_BRANCH $1\" >> \"$0\"".to_string(),
+                    out.to_string_lossy().into_owned(),
+                    "{{STACK_ID}} [{{DEPS}}]".to_string(),
+                ]),
+                run_timeout: "10 seconds".to_string(),
+                run_retry_delay: "0 seconds".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let cli = Cli::parse_from(["mq"]);
+        let vars = EnqueueVars {
+            base_branch: Some("main".to_string()),
+            stack_id: Some("k3x9a".to_string()),
+            deps: Some(vec!["a".to_string(), "b".to_string()]),
+        };
+        enqueue("42", &config, &cli, "", false, None, vars.clone()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "42 main k3x9a [a,b]\n"
+        );
+
+        // Fails every time: one run plus two retries, then an error instead of a panic
+        config.merge.run = RunCommand::Argv(vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "echo attempt >> \"$0\"; exit 1".to_string(),
+            out.to_string_lossy().into_owned(),
+        ]);
+        config.merge.run_retries = 2;
+        std::fs::remove_file(&out).unwrap();
+        let err = enqueue("42", &config, &cli, "", false, None, vars).unwrap_err();
+        assert!(err.contains("after 3 attempt(s)"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&out).unwrap().lines().count(), 3);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_split_labels() {
+        assert_eq!(
+            split_labels("merge-queue, ready ,,"),
+            vec!["merge-queue", "ready"]
+        );
+        assert!(split_labels(" ").is_empty());
+    }
+}
@@ -3,7 +3,8 @@ use crate::config::Conf;
 use crate::github::{GitHub, GitHubAction};
 use regex::Regex;
 use reqwest::header::{HeaderMap, CONTENT_TYPE};
-use serde_json::json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::fs;
 
 /// Extracts dependency targets from a PR body string.
@@ -60,6 +61,75 @@ mod tests {
         let targets = get_targets(body);
         assert_eq!(targets, Vec::<String>::new());
     }
+
+    const QUEUE_JSON: &str = r#"{
+        "state": "RUNNING",
+        "branch": "main",
+        "concurrency": 3,
+        "mode": "SINGLE",
+        "enqueuedPullRequests": [
+            {"prNumber": 12, "prTitle": "a", "state": "TESTING", "priorityName": "high"},
+            {"prNumber": 10, "prTitle": "b", "state": "PENDING", "priorityName": "medium"}
+        ]
+    }"#;
+
+    #[test]
+    fn test_queue_rows() {
+        let queue: QueueInfo = serde_json::from_str(QUEUE_JSON).unwrap();
+        assert_eq!(queue.concurrency, Some(3));
+        let rows = queue_rows(&queue);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].pr_number, 12);
+        assert_eq!(rows[0].position, Some(1));
+        assert_eq!(rows[0].priority.as_deref(), Some("high"));
+        assert_eq!(rows[1].state, "PENDING");
+        assert_eq!(rows[1].targets_uploaded, None);
+    }
+
+    #[test]
+    fn test_submitted_row() {
+        let queue: QueueInfo = serde_json::from_str(QUEUE_JSON).unwrap();
+        let pr: SubmittedPullRequest = serde_json::from_str(
+            r#"{"prNumber": 10, "state": "TESTING", "isCurrentlySubmittedToQueue": true,
+                "readiness": {"hasImpactedTargets": true, "requiresImpactedTargets": true,
+                              "doesBaseBranchMatch": true},
+                "impactedTargets": ["a", "b"], "testingBatch": "batch-3"}"#,
+        )
+        .unwrap();
+        let row = submitted_row(&queue, &pr);
+        assert_eq!(row.position, Some(2));
+        assert_eq!(row.targets_uploaded, Some(true));
+        assert_eq!(row.targets, Some(vec!["a".to_string(), "b".to_string()]));
+        assert_eq!(row.testing_batch.as_deref(), Some("batch-3"));
+
+        let merged = SubmittedPullRequest {
+            pr_number: 7,
+            state: "MERGED".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(submitted_row(&queue, &merged).position, None);
+    }
+
+    #[test]
+    fn test_queue_state_serialization() {
+        assert_eq!(json!(QueueState::Paused), json!("PAUSED"));
+        assert_eq!(json!(QueueState::Running), json!("RUNNING"));
+        assert_eq!(
+            serde_json::from_str::<QueueState>(r#""DRAINING""#).unwrap(),
+            QueueState::Draining
+        );
+    }
+
+    #[test]
+    fn test_is_valid_priority() {
+        assert!(is_valid_priority("urgent"));
+        assert!(is_valid_priority("low"));
+        assert!(is_valid_priority("0"));
+        assert!(is_valid_priority("255"));
+        assert!(!is_valid_priority("256"));
+        assert!(!is_valid_priority("HIGH"));
+        assert!(!is_valid_priority(""));
+    }
 }
 
 pub fn upload_targets(config: &Conf, cli: &Cli, github_json_path: &str) {
@@ -131,6 +201,7 @@ pub fn upload_targets(config: &Conf, cli: &Cli, github_json_path: &str) {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn post_targets(
     repo_owner: &str,
     repo_name: &str,
@@ -161,8 +232,9 @@ pub fn post_targets(
         "impactedTargets": impacted_targets,
     });
 
+    let url = api_url(api, "setImpactedTargets");
     let res = client
-        .post(&format!("https://{}:443/v1/setImpactedTargets", api))
+        .post(&url)
         .headers(headers)
         .body(body.to_string())
         .send()?;
@@ -176,34 +248,13 @@ pub fn post_targets(
 
         // Show debug info on errors
         println!("API request failed:");
-        println!("  URL: https://{}:443/v1/setImpactedTargets", api);
+        println!("  URL: {}", url);
         println!("  Repository: {}/{}", repo_owner, repo_name);
         println!("  PR Number: {}", pr_number);
         println!("  Target Branch: {}", target_branch);
         println!("  Impacted Targets: {:?}", impacted_targets);
 
-        match status.as_u16() {
-            400 => {
-                return Err(format!(
-                    "Bad Request (400): {}. Check request format and parameters.",
-                    error_body
-                )
-                .into())
-            }
-            401 => {
-                return Err(format!("API key rejected (401 Unauthorized): {}", error_body).into())
-            }
-            403 => return Err(format!("API key forbidden (403 Forbidden): {}", error_body).into()),
-            404 => {
-                return Err(
-                    format!("Pull request not found (404 Not Found): {}", error_body).into(),
-                )
-            }
-            429 => {
-                return Err(format!("Rate limited (429 Too Many Requests): {}", error_body).into())
-            }
-            _ => return Err(format!("HTTP error {}: {}", status, error_body).into()),
-        }
+        return Err(status_error(status.as_u16(), "Pull request", &error_body));
     }
 
     Ok(())
@@ -261,7 +312,7 @@ pub fn submit_pull_request(
     let priority_value = priority.unwrap_or("medium");
     body["priority"] = json!(priority_value);
 
-    let url = format!("https://{}/v1/submitPullRequest", api);
+    let url = api_url(api, "submitPullRequest");
     let body_str = body.to_string();
 
     let res = client
@@ -285,29 +336,317 @@ pub fn submit_pull_request(
         println!("  PR Number: {}", pr_number);
         println!("  Target Branch: {}", target_branch);
 
-        match status.as_u16() {
-            400 => {
-                return Err(format!(
-                    "Bad Request (400): {}. Check request format and parameters.",
-                    error_body
-                )
-                .into())
-            }
-            401 => {
-                return Err(format!("API key rejected (401 Unauthorized): {}", error_body).into())
-            }
-            403 => return Err(format!("API key forbidden (403 Forbidden): {}", error_body).into()),
-            404 => {
-                return Err(
-                    format!("Pull request not found (404 Not Found): {}", error_body).into(),
-                )
-            }
-            429 => {
-                return Err(format!("Rate limited (429 Too Many Requests): {}", error_body).into())
-            }
-            _ => return Err(format!("HTTP error {}: {}", status, error_body).into()),
-        }
+        return Err(status_error(status.as_u16(), "Pull request", &error_body));
+    }
+
+    Ok(())
+}
+
+/// Dequeue a PR from the Trunk merge queue (`cancelPullRequest`).
+pub fn cancel_pull_request(
+    repo_owner: &str,
+    repo_name: &str,
+    pr_number: u32,
+    target_branch: &str,
+    api: &str,
+    cli: &Cli,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if cli.trunk_token.is_empty() {
+        return Err("TRUNK_TOKEN is required to dequeue through the Trunk API. Provide it via --trunk-token flag or TRUNK_TOKEN environment variable".into());
+    }
+
+    if cli.dry_run {
+        println!(
+            "dry-run: would cancel PR {} in {}/{} (target: {}) via {}",
+            pr_number, repo_owner, repo_name, target_branch, api
+        );
+        return Ok(());
+    }
+    let client = reqwest::blocking::Client::new();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    headers.insert("x-api-token", cli.trunk_token.parse().unwrap());
+
+    let body = json!({
+        "repo": {
+            "host": "github.com",
+            "owner": repo_owner,
+            "name": repo_name,
+        },
+        "pr": {
+            "number": pr_number,
+        },
+        "targetBranch": target_branch,
+    });
+
+    let url = api_url(api, "cancelPullRequest");
+    let res = client
+        .post(&url)
+        .headers(headers)
+        .body(body.to_string())
+        .send()?;
+
+    if !res.status().is_success() {
+        let status = res.status();
+        let error_body = res
+            .text()
+            .unwrap_or_else(|_| "Unable to read error response".to_string());
+
+        println!("API request failed:");
+        println!("  URL: {}", url);
+        println!("  Repository: {}/{}", repo_owner, repo_name);
+        println!("  PR Number: {}", pr_number);
+        println!("  Target Branch: {}", target_branch);
+
+        return Err(status_error(status.as_u16(), "Pull request", &error_body));
     }
 
     Ok(())
 }
+
+/// A merge queue as returned by `getQueue`. Only the fields mq reports on are modelled.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QueueInfo {
+    pub state: String,
+    pub branch: String,
+    pub mode: Option<String>,
+    pub concurrency: Option<u32>,
+    /// In queue order
+    pub enqueued_pull_requests: Vec<SubmittedPullRequest>,
+}
+
+/// A PR as returned by `getSubmittedPullRequest` and in `getQueue`'s list. `readiness`,
+/// `impacted_targets` and `testing_batch` are only returned for single PRs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SubmittedPullRequest {
+    pub pr_number: u32,
+    pub pr_title: String,
+    pub state: String,
+    pub state_changed_at: Option<String>,
+    pub priority_name: Option<String>,
+    pub priority_value: Option<u32>,
+    pub is_currently_submitted_to_queue: Option<bool>,
+    pub readiness: Option<Readiness>,
+    /// Impacted targets Trunk has for the PR's current head
+    pub impacted_targets: Option<Vec<String>>,
+    /// Batch the PR is being tested in, while it is testing
+    pub testing_batch: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Readiness {
+    pub has_impacted_targets: bool,
+    pub requires_impacted_targets: bool,
+    pub does_base_branch_match: bool,
+}
+
+/// One line of `mq queue status`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PrQueueRow {
+    pub pr_number: u32,
+    pub state: String,
+    /// 1-based position in the queue; None when the PR is not in `getQueue`'s list
+    pub position: Option<usize>,
+    pub priority: Option<String>,
+    /// Batch the PR is being tested in; None when not testing or unknown
+    pub testing_batch: Option<String>,
+    /// Whether Trunk has the PR's impacted targets; None when unknown
+    pub targets_uploaded: Option<bool>,
+    /// Impacted targets Trunk has for the PR
+    pub targets: Option<Vec<String>>,
+    /// Impacted targets listed in the PR body (`deps=[...]`)
+    pub expected_targets: Option<Vec<String>>,
+}
+
+/// Rows for every PR in the queue, in queue order.
+pub fn queue_rows(queue: &QueueInfo) -> Vec<PrQueueRow> {
+    queue
+        .enqueued_pull_requests
+        .iter()
+        .enumerate()
+        .map(|(i, pr)| PrQueueRow {
+            pr_number: pr.pr_number,
+            state: pr.state.clone(),
+            position: Some(i + 1),
+            priority: pr.priority_name.clone(),
+            testing_batch: pr.testing_batch.clone(),
+            targets_uploaded: pr.readiness.as_ref().map(|r| r.has_impacted_targets),
+            targets: pr.impacted_targets.clone(),
+            expected_targets: None,
+        })
+        .collect()
+}
+
+/// The row for one PR looked up with `getSubmittedPullRequest`, positioned by `queue`.
+pub fn submitted_row(queue: &QueueInfo, pr: &SubmittedPullRequest) -> PrQueueRow {
+    PrQueueRow {
+        pr_number: pr.pr_number,
+        state: pr.state.clone(),
+        position: queue
+            .enqueued_pull_requests
+            .iter()
+            .position(|queued| queued.pr_number == pr.pr_number)
+            .map(|i| i + 1),
+        priority: pr.priority_name.clone(),
+        testing_batch: pr.testing_batch.clone(),
+        targets_uploaded: pr.readiness.as_ref().map(|r| r.has_impacted_targets),
+        targets: pr.impacted_targets.clone(),
+        expected_targets: None,
+    }
+}
+
+/// Read the merge queue for `target_branch` (`getQueue`).
+pub fn get_queue(
+    repo_owner: &str,
+    repo_name: &str,
+    target_branch: &str,
+    api: &str,
+    cli: &Cli,
+) -> Result<QueueInfo, Box<dyn std::error::Error>> {
+    let body = json!({
+        "repo": {
+            "host": "github.com",
+            "owner": repo_owner,
+            "name": repo_name,
+        },
+        "targetBranch": target_branch,
+    });
+    let value = post_api(api, "getQueue", "Queue", &body, cli)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Read one PR's state in the merge queue (`getSubmittedPullRequest`).
+pub fn get_submitted_pull_request(
+    repo_owner: &str,
+    repo_name: &str,
+    pr_number: u32,
+    target_branch: &str,
+    api: &str,
+    cli: &Cli,
+) -> Result<SubmittedPullRequest, Box<dyn std::error::Error>> {
+    let body = json!({
+        "repo": {
+            "host": "github.com",
+            "owner": repo_owner,
+            "name": repo_name,
+        },
+        "pr": {
+            "number": pr_number,
+        },
+        "targetBranch": target_branch,
+    });
+    let value = post_api(api, "getSubmittedPullRequest", "Pull request", &body, cli)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Queue states that can be set with `updateQueue`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum QueueState {
+    /// Testing and merging PRs
+    Running,
+    /// Accepting PRs without testing or merging them
+    Paused,
+    /// Finishing the PRs already in the queue without accepting new ones
+    Draining,
+}
+
+/// Set the state of the queue for `target_branch` (`updateQueue`).
+pub fn set_queue_state(
+    repo_owner: &str,
+    repo_name: &str,
+    target_branch: &str,
+    state: QueueState,
+    api: &str,
+    cli: &Cli,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if cli.dry_run {
+        println!(
+            "dry-run: would set the {} queue of {}/{} to {:?} via {}",
+            target_branch, repo_owner, repo_name, state, api
+        );
+        return Ok(());
+    }
+    let body = json!({
+        "repo": {
+            "host": "github.com",
+            "owner": repo_owner,
+            "name": repo_name,
+        },
+        "targetBranch": target_branch,
+        "state": state,
+    });
+    post_api(api, "updateQueue", "Queue", &body, cli)?;
+    Ok(())
+}
+
+/// POST `body` to a Trunk API endpoint and return the JSON response (null for an empty body).
+/// `resource` names what a 404 means was not found.
+fn post_api(
+    api: &str,
+    endpoint: &str,
+    resource: &str,
+    body: &Value,
+    cli: &Cli,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    if cli.trunk_token.is_empty() {
+        return Err("TRUNK_TOKEN is required to use the Trunk API. Provide it via --trunk-token flag or TRUNK_TOKEN environment variable".into());
+    }
+    let client = reqwest::blocking::Client::new();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    headers.insert("x-api-token", cli.trunk_token.parse().unwrap());
+
+    let url = api_url(api, endpoint);
+    let res = client
+        .post(&url)
+        .headers(headers)
+        .body(body.to_string())
+        .send()?;
+
+    let status = res.status();
+    let text = res
+        .text()
+        .unwrap_or_else(|_| "Unable to read error response".to_string());
+    if !status.is_success() {
+        println!("API request failed:");
+        println!("  URL: {}", url);
+        println!("  Request body: {}", body);
+        return Err(status_error(status.as_u16(), resource, &text));
+    }
+    if text.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Named priorities accepted by the Trunk API. A number from 0 (highest) to 255 also works.
+pub const PRIORITIES: [&str; 4] = ["urgent", "high", "medium", "low"];
+
+pub fn is_valid_priority(priority: &str) -> bool {
+    PRIORITIES.contains(&priority) || priority.parse::<u8>().is_ok()
+}
+
+fn api_url(api: &str, endpoint: &str) -> String {
+    format!("https://{}/v1/{}", api, endpoint)
+}
+
+fn status_error(status: u16, resource: &str, error_body: &str) -> Box<dyn std::error::Error> {
+    match status {
+        400 => format!(
+            "Bad Request (400): {}. Check request format and parameters.",
+            error_body
+        ),
+        401 => format!("API key rejected (401 Unauthorized): {}", error_body),
+        403 => format!("API key forbidden (403 Forbidden): {}", error_body),
+        404 => format!("{} not found (404 Not Found): {}", resource, error_body),
+        429 => format!("Rate limited (429 Too Many Requests): {}", error_body),
+        _ => format!("HTTP error {}: {}", status, error_body),
+    }
+    .into()
+}
@@ -1,5 +1,9 @@
 mod test_utils;
-use test_utils::run_mq_with_config;
+use test_utils::run_mq_with_config_and_args;
+
+fn run_mq_with_config(config_content: &str, subcommand: &str) -> (i32, String, String) {
+    run_mq_with_config_and_args(config_content, subcommand, &[])
+}
 
 #[test]
 fn test_invalid_enum_value_error_formatting() {
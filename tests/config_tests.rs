@@ -1,4 +1,8 @@
-use gen::config::{Conf, MergeConf, PullRequestConf, TestConf};
+use gen::config::{
+    Conf, MergeConf, PullRequestConf, StackConf, StackEnqueue, TargetConf, TestConf, TestProfile,
+};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
 mod test_utils;
 use test_utils::run_mq_with_config_and_args;
@@ -6,7 +10,11 @@ use test_utils::run_mq_with_config_and_args;
 /// Helper function to create a test config with valid defaults
 fn create_test_config(pullrequest: PullRequestConf) -> Conf {
     Conf {
-        pullrequest,
+        pullrequest: PullRequestConf {
+            mergeable_poll_timeout: "2 minutes".to_string(), // Valid durations
+            mergeable_poll_interval: "2 seconds".to_string(),
+            ..pullrequest
+        },
         test: TestConf {
             flake_rate: 0.1,                   // Valid flake rate
             sleep_for: "1 second".to_string(), // Valid duration
@@ -105,17 +113,17 @@ fn test_deps_distribution_uniform() {
     // ~50 PRs with 1 dependency, ~30 with 2, ~20 with 3
     // Allow more tolerance for rounding and distribution variations
     assert!(
-        count_1 >= 40 && count_1 <= 60,
+        (40..=60).contains(&count_1),
         "Expected ~50 PRs with 1 dependency, got {}",
         count_1
     );
     assert!(
-        count_2 >= 20 && count_2 <= 40,
+        (20..=40).contains(&count_2),
         "Expected ~30 PRs with 2 dependencies, got {}",
         count_2
     );
     assert!(
-        count_3 >= 10 && count_3 <= 30,
+        (10..=30).contains(&count_3),
         "Expected ~20 PRs with 3 dependencies, got {}",
         count_3
     );
@@ -172,7 +180,7 @@ fn test_edit_files_for_pr_logic() {
     });
 
     // Test the dependency count logic directly
-    let filenames = vec![
+    let filenames = [
         "file1.txt".to_string(),
         "file2.txt".to_string(),
         "file3.txt".to_string(),
@@ -180,7 +188,7 @@ fn test_edit_files_for_pr_logic() {
 
     // Test new distribution approach
     let dependency_count_new = config_new.get_dependency_count(1, filenames.len());
-    assert!(dependency_count_new >= 1 && dependency_count_new <= 2); // Should be 1 or 2 based on distribution
+    assert!((1..=2).contains(&dependency_count_new)); // Should be 1 or 2 based on distribution
 
     // Test old approach
     let dependency_count_old = config_old.get_dependency_count(1, filenames.len());
@@ -450,6 +458,462 @@ fn test_stacks_distribution_validation() {
     }
 }
 
+#[test]
+fn test_mergeable_backoff_doubles_and_caps() {
+    let config = create_test_config(PullRequestConf {
+        mergeable_poll_interval: "2 seconds".to_string(),
+        ..Default::default()
+    });
+
+    assert_eq!(config.mergeable_backoff(0), Duration::from_secs(2));
+    assert_eq!(config.mergeable_backoff(1), Duration::from_secs(4));
+    assert_eq!(config.mergeable_backoff(4), Duration::from_secs(32));
+    // Capped at one minute, including when the multiplier would overflow
+    assert_eq!(config.mergeable_backoff(5), Duration::from_secs(60));
+    assert_eq!(config.mergeable_backoff(40), Duration::from_secs(60));
+}
+
+fn config_with_targets(targets: &[(&str, f32, Option<&str>)]) -> Conf {
+    let mut config = create_test_config(PullRequestConf::default());
+    config.test.targets = Some(
+        targets
+            .iter()
+            .map(|(key, flake_rate, sleep_for)| {
+                (
+                    key.to_string(),
+                    TargetConf {
+                        flake_rate: *flake_rate,
+                        sleep_for: sleep_for.map(|s| s.to_string()),
+                    },
+                )
+            })
+            .collect::<BTreeMap<_, _>>(),
+    );
+    config
+}
+
+#[test]
+fn test_failure_probability_without_targets_is_flake_rate() {
+    let config = create_test_config(PullRequestConf::default());
+    let impacted = vec!["a".to_string(), "b".to_string()];
+
+    assert!((config.failure_probability(&impacted) - 0.1).abs() < 1e-6);
+    assert_eq!(
+        config.sample_test_duration(&impacted, &mut rand::thread_rng()),
+        Duration::from_secs(1)
+    );
+}
+
+#[test]
+fn test_failure_probability_combines_matching_targets() {
+    let config = config_with_targets(&[("p", 0.5, None), ("q", 0.2, None), ("z", 0.9, None)]);
+    let impacted = vec!["p".to_string(), "q".to_string()];
+
+    // 1 - (1 - 0.1) * (1 - 0.5) * (1 - 0.2); "z" is not impacted
+    assert!((config.failure_probability(&impacted) - 0.64).abs() < 1e-6);
+}
+
+#[test]
+fn test_targets_match_changed_file_paths() {
+    let config = config_with_targets(&[("alpha/a.txt", 0.3, Some("5 seconds"))]);
+    let impacted = vec![
+        "test/alpha/a.txt".to_string(),
+        "test/beta/b.txt".to_string(),
+    ];
+
+    let matching = config.matching_targets(&impacted);
+    assert_eq!(matching.len(), 1);
+    assert_eq!(matching[0].0, "alpha/a.txt");
+    assert_eq!(
+        config.sample_test_duration(&impacted, &mut rand::thread_rng()),
+        Duration::from_secs(5)
+    );
+
+    // A bare letter does not match inside a file name
+    let config = config_with_targets(&[("a", 0.3, None)]);
+    assert!(config.matching_targets(&impacted).is_empty());
+}
+
+#[test]
+fn test_sample_test_duration_scales_with_targets() {
+    let mut config = create_test_config(PullRequestConf::default());
+    config.test.sleep_for = "10 seconds".to_string();
+    config.test.sleep_scale_per_target = 0.5;
+    let mut rng = rand::thread_rng();
+
+    let one = vec!["a".to_string()];
+    let three = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+    assert_eq!(
+        config.sample_test_duration(&[], &mut rng),
+        Duration::from_secs(10)
+    );
+    assert_eq!(
+        config.sample_test_duration(&one, &mut rng),
+        Duration::from_secs(10)
+    );
+    assert_eq!(
+        config.sample_test_duration(&three, &mut rng),
+        Duration::from_secs(20)
+    );
+}
+
+#[test]
+fn test_sleep_for_distribution_validation() {
+    let mut config = create_test_config(PullRequestConf::default());
+    for valid in ["1 second", "uniform(30s, 90s)", "lognormal(1m, 0.8)"] {
+        config.test.sleep_for = valid.to_string();
+        assert!(config.is_valid(None).is_ok(), "'{}' should be valid", valid);
+    }
+    for invalid in ["soon", "uniform(90s, 30s)", "poisson(3)"] {
+        config.test.sleep_for = invalid.to_string();
+        assert!(
+            config.is_valid(None).is_err(),
+            "'{}' should be invalid",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_outage_configuration() {
+    let mut config = create_test_config(PullRequestConf::default());
+    assert!(!config.outage_active(std::time::SystemTime::now()));
+
+    config.test.outage_every = Some("1 hour".to_string());
+    config.test.outage_duration = "1 hour".to_string();
+    config.test.outage_flake_rate = 0.9;
+    assert!(config.is_valid(None).is_ok());
+    // A window as long as the period is always active
+    assert!(config.outage_active(std::time::SystemTime::now()));
+
+    config.test.outage_duration = "2 hours".to_string();
+    assert!(config.is_valid(None).is_err());
+
+    config.test.outage_duration = "5 minutes".to_string();
+    config.test.outage_flake_rate = 1.5;
+    assert!(config.is_valid(None).is_err());
+}
+
+#[test]
+fn test_targets_validation() {
+    assert!(config_with_targets(&[("a", 0.3, Some("2 seconds"))])
+        .is_valid(None)
+        .is_ok());
+    assert!(config_with_targets(&[("a", 1.5, None)])
+        .is_valid(None)
+        .is_err());
+    assert!(config_with_targets(&[("a", 0.3, Some("not a duration"))])
+        .is_valid(None)
+        .is_err());
+}
+
+#[test]
+fn test_targets_table_from_toml() {
+    let config = r#"
+[test.targets.p]
+flake_rate = 0.3
+sleep_for = "5 seconds"
+
+[test.targets."alpha/a.txt"]
+flake_rate = 0.05
+
+[merge]
+comment = "/trunk merge"
+"#;
+
+    let (exit_code, stdout, stderr) =
+        run_mq_with_config_and_args(config, "config", &["test.targets.p.flake_rate"]);
+
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.trim().starts_with("0.3"), "stdout: {}", stdout);
+}
+
+fn config_with_release_profile(profile: TestProfile) -> Conf {
+    let mut config = config_with_targets(&[("a", 0.2, None)]);
+    config.test.branches = Some(BTreeMap::from([("release".to_string(), profile)]));
+    config
+}
+
+#[test]
+fn test_apply_branch_profile() {
+    let mut config = config_with_release_profile(TestProfile {
+        flake_rate: Some(0.02),
+        sleep_for: Some("10 minutes".to_string()),
+        targets: Some(BTreeMap::from([(
+            "b".to_string(),
+            TargetConf {
+                flake_rate: 0.5,
+                sleep_for: None,
+            },
+        )])),
+        ..Default::default()
+    });
+
+    assert!(!config.apply_branch_profile("main"));
+    assert!((config.test.flake_rate - 0.1).abs() < 1e-6);
+
+    assert!(config.apply_branch_profile("release"));
+    assert!((config.test.flake_rate - 0.02).abs() < 1e-6);
+    assert_eq!(config.test.sleep_for, "10 minutes");
+    // Unset fields keep the [test] value and targets merge by key
+    assert!((config.test.sleep_scale_per_target - 0.0).abs() < 1e-6);
+    let targets = config.test.targets.as_ref().unwrap();
+    assert_eq!(targets.len(), 2);
+    assert!((targets["a"].flake_rate - 0.2).abs() < 1e-6);
+    assert!((targets["b"].flake_rate - 0.5).abs() < 1e-6);
+}
+
+#[test]
+fn test_branch_profile_validation() {
+    assert!(config_with_release_profile(TestProfile {
+        flake_rate: Some(0.0),
+        timeout: Some("1 hour".to_string()),
+        ..Default::default()
+    })
+    .is_valid(None)
+    .is_ok());
+    assert!(config_with_release_profile(TestProfile {
+        hang_rate: Some(1.5),
+        ..Default::default()
+    })
+    .is_valid(None)
+    .is_err());
+    assert!(config_with_release_profile(TestProfile {
+        sleep_for: Some("uniform(2m, 1m)".to_string()),
+        ..Default::default()
+    })
+    .is_valid(None)
+    .is_err());
+    assert!(config_with_release_profile(TestProfile {
+        timeout: Some("soon".to_string()),
+        ..Default::default()
+    })
+    .is_valid(None)
+    .is_err());
+}
+
+#[test]
+fn test_pick_priority() {
+    let mut rng = rand::thread_rng();
+    let config = create_test_config(PullRequestConf {
+        priorities: vec!["urgent".to_string(), "low".to_string()],
+        ..Default::default()
+    });
+    assert_eq!(config.pick_priority(&mut rng), None);
+
+    let config = create_test_config(PullRequestConf {
+        priority_rate: 1.0,
+        priorities: vec!["urgent".to_string(), "low".to_string()],
+        ..Default::default()
+    });
+    for _ in 0..20 {
+        let priority = config.pick_priority(&mut rng).unwrap();
+        assert!(priority == "urgent" || priority == "low");
+    }
+}
+
+#[test]
+fn test_priority_and_cancel_validation() {
+    let valid = create_test_config(PullRequestConf {
+        priority_rate: 0.2,
+        priorities: vec!["high".to_string(), "10".to_string()],
+        cancel_rate: 0.1,
+        cancel_after: "2 minutes".to_string(),
+        ..Default::default()
+    });
+    assert!(valid.is_valid(None).is_ok());
+
+    let mut config = valid.clone();
+    config.pullrequest.priorities = vec!["highest".to_string()];
+    assert!(config.is_valid(None).is_err());
+
+    let mut config = valid.clone();
+    config.pullrequest.priority_rate = 1.5;
+    assert!(config.is_valid(None).is_err());
+
+    let mut config = valid.clone();
+    config.pullrequest.cancel_after = "later".to_string();
+    assert!(config.is_valid(None).is_err());
+}
+
+#[test]
+fn test_mergeable_poll_validation() {
+    let valid = create_test_config(PullRequestConf::default());
+    assert!(valid.is_valid(None).is_ok());
+
+    let mut config = valid.clone();
+    config.pullrequest.mergeable_poll_timeout = "2 minuets".to_string();
+    assert!(config.is_valid(None).is_err());
+
+    let mut config = valid.clone();
+    config.pullrequest.mergeable_poll_interval = "".to_string();
+    assert!(config.is_valid(None).is_err());
+}
+
+#[test]
+fn test_update_validation() {
+    let valid = create_test_config(PullRequestConf {
+        update_rate: 0.3,
+        update_force_push_rate: 0.5,
+        update_after: "15 minutes".to_string(),
+        ..Default::default()
+    });
+    assert!(valid.is_valid(None).is_ok());
+    assert_eq!(valid.update_after_duration(), Duration::from_secs(15 * 60));
+
+    let mut config = valid.clone();
+    config.pullrequest.update_rate = -0.1;
+    assert!(config.is_valid(None).is_err());
+
+    let mut config = valid.clone();
+    config.pullrequest.update_force_push_rate = 2.0;
+    assert!(config.is_valid(None).is_err());
+
+    let mut config = valid.clone();
+    config.pullrequest.update_after = "whenever".to_string();
+    assert!(config.is_valid(None).is_err());
+
+    // update_after is only read when PRs get updated
+    config.pullrequest.update_rate = 0.0;
+    assert!(config.is_valid(None).is_ok());
+}
+
+#[test]
+fn test_enqueue_delay_validation() {
+    let valid = create_test_config(PullRequestConf {
+        enqueue_delay: Some("lognormal(20m, 0.8)".to_string()),
+        enqueue_wait_for_checks: true,
+        enqueue_checks_timeout: "1 hour".to_string(),
+        ..Default::default()
+    });
+    assert!(valid.is_valid(None).is_ok());
+    assert!(valid
+        .sample_enqueue_delay(&mut rand::thread_rng())
+        .is_some());
+
+    let mut config = valid.clone();
+    config.pullrequest.enqueue_delay = Some("lognormal(20m)".to_string());
+    assert!(config.is_valid(None).is_err());
+
+    let mut config = valid.clone();
+    config.pullrequest.enqueue_checks_timeout = "soon".to_string();
+    assert!(config.is_valid(None).is_err());
+
+    // The timeout only matters when waiting for checks
+    config.pullrequest.enqueue_wait_for_checks = false;
+    assert!(config.is_valid(None).is_ok());
+    config.pullrequest.enqueue_delay = None;
+    assert!(config
+        .sample_enqueue_delay(&mut rand::thread_rng())
+        .is_none());
+}
+
+#[test]
+fn test_branch_profiles_from_toml() {
+    let config = r#"
+[test.branches.release]
+flake_rate = 0.02
+sleep_for = "10 minutes"
+
+[merge]
+comment = "/trunk merge"
+"#;
+
+    let (exit_code, stdout, stderr) =
+        run_mq_with_config_and_args(config, "config", &["test.branches.release.sleep_for"]);
+
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(stdout.contains("10 minutes"), "stdout: {}", stdout);
+}
+
+#[test]
+fn test_github_trigger_from_toml() {
+    let config = r#"
+[merge]
+trigger = "github"
+github_method = "auto-merge"
+"#;
+
+    let (exit_code, stdout, stderr) = run_mq_with_config_and_args(config, "config", &["merge"]);
+
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(
+        stdout.contains(r#""trigger":"github""#),
+        "stdout: {}",
+        stdout
+    );
+    assert!(
+        stdout.contains(r#""github_method":"auto-merge""#),
+        "stdout: {}",
+        stdout
+    );
+}
+
+#[test]
+fn test_queue_backend_triggers_from_toml() {
+    for trigger in ["mergify", "bors"] {
+        let config = format!("[merge]\ntrigger = \"{}\"\n", trigger);
+        let (exit_code, stdout, stderr) =
+            run_mq_with_config_and_args(&config, "config", &["merge"]);
+        assert_eq!(exit_code, 0, "stderr: {}", stderr);
+        assert!(
+            stdout.contains(&format!(r#""trigger":"{}""#, trigger)),
+            "stdout: {}",
+            stdout
+        );
+    }
+
+    // Graphite is label-driven and needs the label its merge queue watches
+    let (exit_code, _, _) =
+        run_mq_with_config_and_args("[merge]\ntrigger = \"graphite\"\n", "config", &["merge"]);
+    assert_ne!(exit_code, 0);
+    let (exit_code, _, stderr) = run_mq_with_config_and_args(
+        "[merge]\ntrigger = \"graphite\"\nlabels = \"merge-queue\"\n",
+        "config",
+        &["merge"],
+    );
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+}
+
+#[test]
+fn test_stack_enqueue_from_toml() {
+    for strategy in ["top", "each", "bottom-up", "random-order"] {
+        let config = format!(
+            "[merge]\ncomment = \"/trunk merge\"\n\n[stack]\nenqueue = \"{}\"\n",
+            strategy
+        );
+        let (exit_code, stdout, stderr) =
+            run_mq_with_config_and_args(&config, "config", &["stack.enqueue"]);
+        assert_eq!(exit_code, 0, "stderr: {}", stderr);
+        assert!(stdout.contains(strategy), "stdout: {}", stdout);
+    }
+
+    let (exit_code, _, _) = run_mq_with_config_and_args(
+        "[merge]\ncomment = \"/trunk merge\"\n\n[stack]\nenqueue = \"sideways\"\n",
+        "config",
+        &["stack"],
+    );
+    assert_ne!(exit_code, 0);
+}
+
+#[test]
+fn test_stack_enqueue_interval_validation() {
+    let mut config = create_test_config(PullRequestConf::default());
+    config.stack = StackConf {
+        enqueue: StackEnqueue::BottomUp,
+        enqueue_interval: "45 seconds".to_string(),
+    };
+    assert!(config.is_valid(None).is_ok());
+    assert_eq!(config.stack_enqueue_interval(), Duration::from_secs(45));
+
+    config.stack.enqueue_interval = "eventually".to_string();
+    assert!(config.is_valid(None).is_err());
+
+    // Only the strategies that space out their enqueues use the interval
+    config.stack.enqueue = StackEnqueue::Each;
+    assert!(config.is_valid(None).is_ok());
+}
+
 #[test]
 fn test_config_full_output() {
     let config = r#"
@@ -585,3 +1049,26 @@ trigger = "api"
     // Should output full JSON config
     assert!(stdout.contains("\"trunk\""), "Should contain trunk section");
 }
+
+#[test]
+fn test_run_trigger_argv_from_toml() {
+    let config = r#"
+[merge]
+trigger = "run"
+run = ["gh", "pr", "merge", "{{PR_NUMBER}}", "--subject", "queued by mq"]
+run_timeout = "1 minute"
+"#;
+
+    let (exit_code, stdout, stderr) = run_mq_with_config_and_args(config, "config", &["merge"]);
+    assert_eq!(exit_code, 0, "stderr: {}", stderr);
+    assert!(
+        stdout.contains(r#""run":["gh","pr","merge","{{PR_NUMBER}}","--subject","queued by mq"]"#),
+        "stdout: {}",
+        stdout
+    );
+
+    // Shell mode runs a command line, not an argv array
+    let config = format!("{}run_shell = true\n", config);
+    let (exit_code, _, _) = run_mq_with_config_and_args(&config, "config", &["merge"]);
+    assert_ne!(exit_code, 0);
+}
@@ -1,4 +1,7 @@
-use gen::github::GitHubAction;
+use gen::github::{
+    parse_enqueue_response, parse_pending_required_checks, parse_pr_page, GitHubAction,
+    GitHubContext,
+};
 use gen::trunk::get_targets;
 
 #[test]
@@ -137,3 +140,140 @@ fn test_parse_deps_empty_brackets() {
     assert_eq!(impacted_targets.len(), 1);
     assert_eq!(impacted_targets[0], "");
 }
+
+#[test]
+fn test_parse_pr_page_with_next_cursor() {
+    let page = r#"{
+        "data": {
+            "repository": {
+                "pullRequests": {
+                    "pageInfo": { "hasNextPage": true, "endCursor": "Y3Vyc29yOjEwMA==" },
+                    "nodes": [
+                        {
+                            "number": 41,
+                            "mergeable": "MERGEABLE",
+                            "headRefName": "stack-change/a1f3c-1",
+                            "baseRefName": "main",
+                            "comments": { "nodes": [
+                                { "body": "/trunk merge", "createdAt": "2024-01-01T00:00:00Z" }
+                            ] }
+                        },
+                        {
+                            "number": 42,
+                            "mergeable": "UNKNOWN",
+                            "headRefName": "feature",
+                            "baseRefName": "develop",
+                            "comments": { "nodes": [] }
+                        }
+                    ]
+                }
+            }
+        }
+    }"#;
+
+    let (prs, cursor) = parse_pr_page(page).unwrap();
+
+    assert_eq!(cursor.as_deref(), Some("Y3Vyc29yOjEwMA=="));
+    assert_eq!(prs.len(), 2);
+    assert_eq!(prs[0].number, 41);
+    assert_eq!(prs[0].mergeable, "MERGEABLE");
+    assert_eq!(prs[0].head_ref_name, "stack-change/a1f3c-1");
+    assert_eq!(prs[0].comments.len(), 1);
+    assert_eq!(prs[0].comments[0].body, "/trunk merge");
+    assert_eq!(prs[0].comments[0].created_at, "2024-01-01T00:00:00Z");
+    assert_eq!(prs[1].base_ref_name, "develop");
+    assert!(prs[1].comments.is_empty());
+}
+
+#[test]
+fn test_parse_pr_page_last_page() {
+    let page = r#"{
+        "data": {
+            "repository": {
+                "pullRequests": {
+                    "pageInfo": { "hasNextPage": false, "endCursor": "Y3Vyc29yOjIwMA==" },
+                    "nodes": [ { "number": 7, "mergeable": "CONFLICTING" } ]
+                }
+            }
+        }
+    }"#;
+
+    let (prs, cursor) = parse_pr_page(page).unwrap();
+
+    assert_eq!(cursor, None);
+    assert_eq!(prs.len(), 1);
+    assert_eq!(prs[0].number, 7);
+    assert!(prs[0].comments.is_empty());
+}
+
+#[test]
+fn test_parse_pr_page_graphql_errors() {
+    let page = r#"{ "errors": [ { "message": "Could not resolve to a Repository" } ] }"#;
+
+    let err = parse_pr_page(page).unwrap_err();
+
+    assert!(err.contains("Could not resolve to a Repository"));
+}
+
+#[test]
+fn test_context_base_branch() {
+    let pull_request =
+        GitHubContext::from_json(r#"{"event_name": "pull_request", "base_ref": "release"}"#)
+            .unwrap();
+    assert_eq!(pull_request.base_branch(), Some("release"));
+
+    let merge_group = GitHubContext::from_json(
+        r#"{"event_name": "merge_group", "base_ref": "",
+            "event": {"merge_group": {"base_ref": "refs/heads/develop"}}}"#,
+    )
+    .unwrap();
+    assert_eq!(merge_group.base_branch(), Some("develop"));
+
+    let push =
+        GitHubContext::from_json(r#"{"event_name": "push", "ref": "refs/heads/main"}"#).unwrap();
+    assert_eq!(push.base_branch(), None);
+}
+
+#[test]
+fn test_parse_enqueue_response() {
+    let entry = parse_enqueue_response(
+        r#"{"data": {"enqueuePullRequest": {"mergeQueueEntry": {"position": 3, "state": "QUEUED"}}}}"#,
+    )
+    .unwrap();
+    assert_eq!(entry.position, Some(3));
+    assert_eq!(entry.state, "QUEUED");
+
+    let err = parse_enqueue_response(
+        r#"{"data": {"enqueuePullRequest": null}, "errors": [{"message": "Merge queue is not enabled"}, {"message": "second"}]}"#,
+    )
+    .unwrap_err();
+    assert_eq!(err, "Merge queue is not enabled; second");
+
+    assert!(parse_enqueue_response(r#"{"data": {"enqueuePullRequest": null}}"#).is_err());
+}
+
+#[test]
+fn test_parse_pending_required_checks() {
+    let response = r#"{"data": {"repository": {"pullRequest": {"commits": {"nodes": [{"commit": {
+        "statusCheckRollup": {"contexts": {"nodes": [
+            {"__typename": "CheckRun", "name": "build", "status": "COMPLETED", "isRequired": true},
+            {"__typename": "CheckRun", "name": "test", "status": "IN_PROGRESS", "isRequired": true},
+            {"__typename": "CheckRun", "name": "lint", "status": "QUEUED", "isRequired": false},
+            {"__typename": "StatusContext", "context": "ci/legacy", "state": "PENDING", "isRequired": true},
+            {"__typename": "StatusContext", "context": "ci/done", "state": "FAILURE", "isRequired": true}
+        ]}}}}]}}}}}"#;
+    assert_eq!(
+        parse_pending_required_checks(response).unwrap(),
+        vec!["test", "ci/legacy"]
+    );
+
+    let no_checks = r#"{"data": {"repository": {"pullRequest": {"commits": {"nodes": [
+        {"commit": {"statusCheckRollup": null}}]}}}}}"#;
+    assert!(parse_pending_required_checks(no_checks).unwrap().is_empty());
+
+    let err = parse_pending_required_checks(
+        r#"{"errors": [{"message": "Could not resolve to a PullRequest"}]}"#,
+    )
+    .unwrap_err();
+    assert!(err.contains("Could not resolve to a PullRequest"));
+}
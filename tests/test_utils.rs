@@ -24,10 +24,6 @@ pub fn get_binary_path() -> PathBuf {
     path
 }
 
-pub fn run_mq_with_config(config_content: &str, subcommand: &str) -> (i32, String, String) {
-    run_mq_with_config_and_args(config_content, subcommand, &[])
-}
-
 pub fn run_mq_with_config_and_args(
     config_content: &str,
     subcommand: &str,